@@ -0,0 +1,362 @@
+use crate::cli::{self, BridgeOptions};
+use crate::config::Robot;
+use futures::select;
+use futures::stream::StreamExt;
+use paho_mqtt::{AsyncClient, MessageBuilder};
+use roomba::{api, Client, ConnectionState, State, Status};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+
+/// Option of the room select entity to clean the whole home.
+const WHOLE_HOME: &str = "Whole home";
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Topics {
+    state: String,
+    command: String,
+    room: String,
+    room_set: String,
+    availability: String,
+}
+
+impl Topics {
+    fn new(prefix: &str) -> Self {
+        Self {
+            state: format!("{}/state", prefix),
+            command: format!("{}/command", prefix),
+            room: format!("{}/room", prefix),
+            room_set: format!("{}/room/set", prefix),
+            availability: format!("{}/availability", prefix),
+        }
+    }
+}
+
+struct Bridge<'a> {
+    broker: AsyncClient,
+    topics: Topics,
+    id: String,
+    name: &'a str,
+    robot: &'a Robot,
+    options: &'a BridgeOptions,
+    room: Option<String>,
+}
+
+/// Publish the state of the robot to an MQTT broker with the Home Assistant discovery payloads and
+/// forward the commands received from the broker.
+pub async fn run(
+    client: &mut Client,
+    name: &str,
+    robot: &Robot,
+    options: &BridgeOptions,
+) -> Result<(), Box<dyn Error>> {
+    let prefix = options
+        .topic_prefix
+        .clone()
+        .unwrap_or_else(|| format!("roomba/{}", name));
+    let topics = Topics::new(&prefix);
+    let id = robot.blid.clone().unwrap_or_else(|| name.to_string());
+
+    let opts = paho_mqtt::CreateOptionsBuilder::new()
+        .server_uri(options.broker.as_str())
+        .client_id(format!("roomba-{}", id))
+        .finalize();
+    let mut broker = AsyncClient::new(opts)?;
+    let mut broker_events = broker.get_stream(100);
+
+    let mut bridge = Bridge {
+        broker,
+        topics,
+        id,
+        name,
+        robot,
+        options,
+        room: None,
+    };
+    bridge.connect().await?;
+
+    let mut state = State::default();
+    let mut last_state = String::new();
+
+    loop {
+        select! {
+            message = client.events.next() => match message {
+                Some(Some(message)) => {
+                    if state.update(&message).is_some() && state.is_synced() {
+                        let payload = state_payload(&state.status()).to_string();
+                        if payload != last_state {
+                            // published again on the next change if the broker is not reachable
+                            match bridge.publish(&bridge.topics.state, &payload).await {
+                                Ok(()) => last_state = payload,
+                                Err(err) => warn!("could not publish the state: {}", err),
+                            }
+                        }
+                    }
+                }
+                Some(None) => {
+                    let _ = bridge.publish(&bridge.topics.availability, "offline").await;
+                    return Err("the connection to the robot has been lost".into());
+                }
+                None => break,
+            },
+            connection = client.states.next() => if let Some(connection) = connection {
+                warn!("connection to the robot: {}", connection);
+                if connection == ConnectionState::Connected {
+                    last_state.clear();
+                }
+                if let Err(err) = bridge.publish_availability(connection).await {
+                    warn!("could not publish the availability: {}", err);
+                }
+            },
+            message = broker_events.next() => match message {
+                Some(Some(message)) => {
+                    let payload = message.payload_str();
+                    if message.topic() == bridge.topics.room_set {
+                        if let Err(err) = bridge.select_room(&payload).await {
+                            warn!("could not publish the room: {}", err);
+                        }
+                    } else if message.topic() == bridge.topics.command {
+                        let res = match bridge.command(&payload) {
+                            Ok(command) => client.send_message(&command).await.map_err(|err| err.to_string()),
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = res {
+                            warn!("could not handle command {:?}: {}", payload, err);
+                        }
+                    }
+                }
+                Some(None) => {
+                    warn!("connection to the broker lost");
+                    while let Err(err) = bridge.reconnect().await {
+                        debug!("could not reconnect to the broker: {}", err);
+                        async_std::task::sleep(RECONNECT_INTERVAL).await;
+                    }
+                    // the reconnection marks the robot online
+                    let connection = client.connection_state();
+                    if connection != ConnectionState::Connected {
+                        if let Err(err) = bridge.publish_availability(connection).await {
+                            warn!("could not publish the availability: {}", err);
+                        }
+                    }
+                    // the robot only sends the changes of its state
+                    last_state.clear();
+                }
+                None => break,
+            },
+            complete => break,
+        }
+    }
+
+    Err("the connection has been closed".into())
+}
+
+impl<'a> Bridge<'a> {
+    async fn connect(&self) -> paho_mqtt::Result<()> {
+        let will = MessageBuilder::new()
+            .topic(self.topics.availability.as_str())
+            .payload("offline")
+            .qos(1)
+            .retained(true)
+            .finalize();
+        let mut conn_opts = paho_mqtt::ConnectOptionsBuilder::new();
+        conn_opts.will_message(will);
+        if let Some(username) = self.options.username.as_ref() {
+            conn_opts.user_name(username.as_str());
+        }
+        if let Some(password) = self.options.password.as_ref() {
+            conn_opts.password(password.as_str());
+        }
+
+        self.broker.connect(conn_opts.finalize()).await?;
+        self.subscribe().await
+    }
+
+    async fn reconnect(&self) -> paho_mqtt::Result<()> {
+        self.broker.reconnect().await?;
+        self.subscribe().await
+    }
+
+    async fn subscribe(&self) -> paho_mqtt::Result<()> {
+        self.broker
+            .subscribe_many(&[&self.topics.command, &self.topics.room_set], &[1, 1])
+            .await?;
+
+        for (topic, payload) in self.discovery() {
+            self.publish(&topic, &payload.to_string()).await?;
+        }
+        self.publish(
+            &self.topics.room,
+            self.room.as_deref().unwrap_or(WHOLE_HOME),
+        )
+        .await?;
+        self.publish(&self.topics.availability, "online").await
+    }
+
+    async fn publish_availability(&self, connection: ConnectionState) -> paho_mqtt::Result<()> {
+        let availability = if connection == ConnectionState::Connected {
+            "online"
+        } else {
+            "offline"
+        };
+
+        self.publish(&self.topics.availability, availability).await
+    }
+
+    async fn publish(&self, topic: &str, payload: &str) -> paho_mqtt::Result<()> {
+        let message = MessageBuilder::new()
+            .topic(topic)
+            .payload(payload)
+            .qos(1)
+            .retained(true)
+            .finalize();
+
+        self.broker.publish(message).await
+    }
+
+    async fn select_room(&mut self, name: &str) -> paho_mqtt::Result<()> {
+        if name == WHOLE_HOME {
+            self.room = None;
+        } else if self.robot.regions().any(|x| x.name == name) {
+            self.room = Some(name.to_string());
+        } else {
+            warn!("unknown room {:?}", name);
+        }
+
+        self.publish(
+            &self.topics.room,
+            self.room.as_deref().unwrap_or(WHOLE_HOME),
+        )
+        .await
+    }
+
+    /// Convert a command of the vacuum entity to a message for the robot. The command is either
+    /// the payload itself or the `command` of a JSON payload (`vacuum.send_command`).
+    fn command(&self, payload: &str) -> Result<api::Message, String> {
+        let command = serde_json::from_str::<Value>(payload)
+            .ok()
+            .and_then(|x| x.get("command").and_then(Value::as_str).map(String::from))
+            .unwrap_or_else(|| payload.to_string());
+
+        let command = match command.as_str() {
+            "start" => match self.room.as_ref() {
+                Some(room) => cli::Command::StartRegions {
+                    ordered: false,
+                    regions: vec![room.clone()],
+                },
+                None => cli::Command::Start,
+            },
+            "pause" => cli::Command::Pause,
+            "stop" => cli::Command::Stop,
+            "return_to_base" | "dock" => cli::Command::Dock,
+            "clean" => cli::Command::Clean,
+            "resume" => cli::Command::Resume,
+            "evac" => cli::Command::Evac,
+            "train" => cli::Command::Train,
+            other => return Err(format!("unknown command `{}`", other)),
+        };
+        let (command, extra) = command.into_command_with_extra(self.robot)?;
+
+        Ok(api::Message::new_command(command, extra))
+    }
+
+    fn discovery(&self) -> Vec<(String, Value)> {
+        let prefix = &self.options.discovery_prefix;
+        let id = &self.id;
+        let device = json!({
+            "identifiers": [id],
+            "name": self.name,
+            "manufacturer": "iRobot",
+        });
+        let options: Vec<&str> = std::iter::once(WHOLE_HOME)
+            .chain(self.robot.regions().map(|x| x.name.as_str()))
+            .collect();
+
+        vec![
+            (
+                format!("{}/vacuum/{}/config", prefix, id),
+                json!({
+                    "name": self.name,
+                    "unique_id": id,
+                    "schema": "state",
+                    "state_topic": self.topics.state,
+                    "command_topic": self.topics.command,
+                    "send_command_topic": self.topics.command,
+                    "availability_topic": self.topics.availability,
+                    "supported_features": [
+                        "start", "stop", "pause", "return_home", "battery", "status",
+                        "send_command",
+                    ],
+                    "device": device,
+                }),
+            ),
+            (
+                format!("{}/sensor/{}_battery/config", prefix, id),
+                json!({
+                    "name": format!("{} battery", self.name),
+                    "unique_id": format!("{}_battery", id),
+                    "state_topic": self.topics.state,
+                    "value_template": "{{ value_json.battery }}",
+                    "unit_of_measurement": "%",
+                    "device_class": "battery",
+                    "availability_topic": self.topics.availability,
+                    "device": device,
+                }),
+            ),
+            (
+                format!("{}/sensor/{}_error/config", prefix, id),
+                json!({
+                    "name": format!("{} error", self.name),
+                    "unique_id": format!("{}_error", id),
+                    "state_topic": self.topics.state,
+                    "value_template": "{{ value_json.error }}",
+                    "availability_topic": self.topics.availability,
+                    "device": device,
+                }),
+            ),
+            (
+                format!("{}/binary_sensor/{}_bin/config", prefix, id),
+                json!({
+                    "name": format!("{} bin full", self.name),
+                    "unique_id": format!("{}_bin", id),
+                    "state_topic": self.topics.state,
+                    "value_template": "{{ 'ON' if value_json.bin_full else 'OFF' }}",
+                    "device_class": "problem",
+                    "availability_topic": self.topics.availability,
+                    "device": device,
+                }),
+            ),
+            (
+                format!("{}/select/{}_room/config", prefix, id),
+                json!({
+                    "name": format!("{} room", self.name),
+                    "unique_id": format!("{}_room", id),
+                    "state_topic": self.topics.room,
+                    "command_topic": self.topics.room_set,
+                    "options": options,
+                    "availability_topic": self.topics.availability,
+                    "device": device,
+                }),
+            ),
+        ]
+    }
+}
+
+/// The status of the robot with the state and battery level expected by the vacuum entity.
+fn state_payload(status: &Status) -> Value {
+    let state = if status.is_stuck() {
+        "error"
+    } else {
+        match (status.cycle.as_deref(), status.phase.as_deref()) {
+            (_, Some("run")) => "cleaning",
+            (_, Some("hmUsrDock")) | (_, Some("hmPostMsn")) | (_, Some("hmMidMsn")) => "returning",
+            (Some(cycle), Some("stop")) if cycle != "none" => "paused",
+            _ if status.docked => "docked",
+            _ => "idle",
+        }
+    };
+
+    let mut payload = serde_json::to_value(status).expect("serialization failed");
+    payload["state"] = state.into();
+    payload["battery_level"] = status.battery.into();
+    payload
+}
@@ -1,18 +1,62 @@
 use crate::api;
+use crate::config::Robot;
+use roomba::Watchdog;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 pub struct Cli {
+    /// Path to the configuration file (defaults to `roomba.toml` in the configuration directory).
+    #[structopt(long, global = true, env = "ROOMBA_CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Name of the robot profile to use (defaults to `default_robot` of the configuration).
+    #[structopt(long, global = true)]
+    pub robot: Option<String>,
+    #[structopt(flatten)]
+    pub session: SessionOptions,
     #[structopt(subcommand)]
     pub command: AnyCommand,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct SessionOptions {
+    /// Record every MQTT message exchanged with the robot to a JSON Lines file.
+    #[structopt(long, global = true, parse(from_os_str))]
+    pub record: Option<PathBuf>,
+    /// Replay a recorded session instead of connecting to the robot.
+    #[structopt(long, global = true, parse(from_os_str))]
+    pub replay: Option<PathBuf>,
+    /// Speed factor of the replay (2 is twice as fast as the original session).
+    #[structopt(long, global = true, default_value = "1")]
+    pub speed: f64,
+    /// Ping the robot after this many seconds without traffic (MQTT keep-alive) and report the
+    /// lost connections.
+    #[structopt(long, global = true)]
+    pub watchdog: Option<u64>,
+    /// Reconnect automatically when the connection is lost.
+    #[structopt(long, global = true, requires = "watchdog")]
+    pub reconnect: bool,
+}
+
+impl SessionOptions {
+    /// Watchdog requested with `--watchdog`.
+    pub fn watchdog(&self) -> Option<Watchdog> {
+        self.watchdog.map(|timeout| Watchdog {
+            timeout: Duration::from_secs(timeout),
+            reconnect: self.reconnect,
+        })
+    }
+}
+
 #[derive(StructOpt, Debug)]
 pub enum AnyCommand {
     #[structopt(name = "command")]
     Authenticated(AuthenticatedCommand),
     #[structopt(flatten)]
     Unauthenticated(UnauthenticatedCommand),
+    #[structopt(flatten)]
+    Connected(ConnectedCommand),
 }
 
 #[derive(StructOpt, Debug)]
@@ -28,13 +72,24 @@ pub enum Command {
     StartRegions {
         #[structopt(long)]
         ordered: bool,
-        #[structopt(parse(from_str), min_values = 1)]
-        regions: Vec<api::Region>,
+        /// Names of the rooms and zones in the configuration or raw region ids.
+        #[structopt(min_values = 1)]
+        regions: Vec<String>,
     },
 }
 
 #[derive(StructOpt, Debug)]
 pub struct AuthenticatedCommand {
+    /// Send the command to every robot of the configuration.
+    #[structopt(long)]
+    pub all: bool,
+    /// Wait until the mission ends. Exits with 2 if the robot gets stuck or in error, with 4 if
+    /// the mission is cancelled and with 5 on timeout.
+    #[structopt(long)]
+    pub wait: bool,
+    /// Seconds to wait for the end of the mission (requires `--wait`).
+    #[structopt(long, requires = "wait")]
+    pub timeout: Option<u64>,
     #[structopt(subcommand)]
     pub command: Option<Command>,
 }
@@ -44,28 +99,177 @@ pub enum UnauthenticatedCommand {
     FindIp {
         #[structopt(long)]
         no_save: bool,
+        /// Print the robots found as JSON, nothing is saved.
+        #[structopt(long)]
+        json: bool,
+        /// Time to wait for the robots to answer, in seconds.
+        #[structopt(long, default_value = "5")]
+        timeout: u64,
     },
     GetPassword {
         hostname: Option<String>,
         #[structopt(long)]
         no_save: bool,
     },
+    /// Diagnose the connection to the robot step by step.
+    Doctor {
+        /// Time to wait for the connection and the state of the robot, in seconds.
+        #[structopt(long, default_value = "10")]
+        timeout: u64,
+    },
+    /// Find, pair and configure a robot step by step.
+    Setup {
+        /// Time to wait for the robots to answer, in seconds.
+        #[structopt(long, default_value = "5")]
+        timeout: u64,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ConnectedCommand {
+    /// Share the connection to the robot with other MQTT clients through a local endpoint.
+    Proxy {
+        #[structopt(long, default_value = "127.0.0.1:1883")]
+        listen: String,
+        /// PEM certificate chain to serve the endpoint over TLS (requires `--tls-key`).
+        #[structopt(long, parse(from_os_str))]
+        tls_cert: Option<PathBuf>,
+        /// PEM private key of the TLS certificate.
+        #[structopt(long, parse(from_os_str))]
+        tls_key: Option<PathBuf>,
+        /// Username required from the clients (requires `--password`).
+        #[structopt(long)]
+        username: Option<String>,
+        /// Password required from the clients.
+        #[structopt(long, env = "ROOMBA_PROXY_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Print the state of the robot. Exits with 2 if it is stuck or in error and with 3 if it
+    /// needs attention.
+    Status {
+        #[structopt(long)]
+        json: bool,
+        /// Seconds to wait for the state of the robot.
+        #[structopt(long, default_value = "10")]
+        timeout: u64,
+    },
+    /// Manage the rooms of the configuration.
+    Rooms(RoomsCommand),
+    /// Send raw MQTT messages to the robot.
+    Raw(RawCommand),
+    /// Print the full reported state of the robot as JSON.
+    Dump {
+        /// Seconds to wait for the state of the robot.
+        #[structopt(long, default_value = "10")]
+        timeout: u64,
+    },
+    /// Keep the connection open and start the cleaning jobs of the schedules of the robot.
+    Daemon,
+    /// Serve the state of the robot and accept commands over HTTP.
+    Serve {
+        #[structopt(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Expose the state of the robot as Prometheus metrics on `/metrics`.
+    Metrics {
+        #[structopt(long, default_value = "127.0.0.1:9887")]
+        listen: String,
+    },
+    /// Bridge the robot to Home Assistant through an MQTT broker.
+    Bridge(BridgeOptions),
+    /// Stream every message of the robot as newline-delimited JSON.
+    Watch {
+        /// Only stream the high-level events (mission started, bin full, stuck, ...).
+        #[structopt(long)]
+        events: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub struct BridgeOptions {
+    /// URI of the MQTT broker.
+    #[structopt(long, default_value = "tcp://127.0.0.1:1883")]
+    pub broker: String,
+    #[structopt(long)]
+    pub username: Option<String>,
+    #[structopt(long, env = "ROOMBA_BROKER_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+    /// Prefix of the topics of the robot (defaults to `roomba/<robot>`).
+    #[structopt(long)]
+    pub topic_prefix: Option<String>,
+    /// Prefix of the Home Assistant discovery topics.
+    #[structopt(long, default_value = "homeassistant")]
+    pub discovery_prefix: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum RawCommand {
+    /// Publish a JSON payload on a topic of the robot.
+    Publish {
+        #[structopt(long)]
+        topic: String,
+        payload: String,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum RoomsCommand {
+    /// Name the region ids of the missions started with the app and save them as rooms and zones.
+    /// Only the map of the profile is learned.
+    Learn,
+}
+
+impl ConnectedCommand {
+    /// Watchdog used when `--watchdog` is not given: the long-running commands reconnect by
+    /// themselves.
+    pub fn default_watchdog(&self) -> Option<Watchdog> {
+        match self {
+            ConnectedCommand::Proxy { .. }
+            | ConnectedCommand::Daemon
+            | ConnectedCommand::Serve { .. }
+            | ConnectedCommand::Metrics { .. }
+            | ConnectedCommand::Bridge(_) => Some(Watchdog {
+                timeout: Duration::from_secs(60),
+                reconnect: true,
+            }),
+            _ => None,
+        }
+    }
 }
 
 impl Command {
+    /// Whether the command starts a mission that can be waited for.
+    pub fn is_mission(&self) -> bool {
+        matches!(
+            self,
+            Command::Start
+                | Command::Clean
+                | Command::Resume
+                | Command::Train
+                | Command::StartRegions { .. }
+        )
+    }
+
     pub fn into_command_with_extra(
         self,
-        pmap_id: &str,
-        user_pmapv_id: &str,
-    ) -> (api::Command, Option<api::Extra>) {
-        match self {
+        robot: &Robot,
+    ) -> Result<(api::Command, Option<api::Extra>), String> {
+        Ok(match self {
             Command::StartRegions { ordered, regions } => (
                 api::Command::Start,
                 Some(api::Extra::StartRegions {
-                    pmap_id: pmap_id.to_string(),
-                    user_pmapv_id: user_pmapv_id.to_string(),
+                    pmap_id: robot.pmap_id.clone().ok_or(
+                        "Missing pmap_id in the configuration. Please run `rooms learn` first",
+                    )?,
+                    user_pmapv_id: robot.user_pmapv_id.clone().ok_or(
+                        "Missing user_pmapv_id in the configuration. Please run `rooms learn` \
+                        first",
+                    )?,
                     ordered: ordered.into(),
-                    regions,
+                    regions: regions
+                        .iter()
+                        .map(|x| resolve_region(x, robot))
+                        .collect::<Result<_, _>>()?,
                 }),
             ),
             Command::Start => (api::Command::Start, None),
@@ -76,6 +280,215 @@ impl Command {
             Command::Dock => (api::Command::Dock, None),
             Command::Evac => (api::Command::Evac, None),
             Command::Train => (api::Command::Train, None),
+        })
+    }
+}
+
+/// Find a region by the name of the room or zone (case-insensitive) or by its raw id.
+fn resolve_region(name: &str, robot: &Robot) -> Result<api::Region, String> {
+    let lowercase = name.to_lowercase();
+    let by_name: Vec<_> = robot
+        .regions()
+        .filter(|x| x.name.to_lowercase() == lowercase)
+        .collect();
+    match by_name.as_slice() {
+        [room] => return Ok(room.region.clone()),
+        [] => {}
+        _ => {
+            return Err(format!(
+                "Ambiguous name `{}`, rename one of: {}",
+                name,
+                by_name
+                    .iter()
+                    .map(|x| match x.region.type_.as_str() {
+                        "zid" => format!("zone `{}`", x.name),
+                        _ => format!("room `{}`", x.name),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
         }
     }
+
+    // rooms and zones have their own ids: the same id can be a room and a zone
+    let by_id: Vec<_> = robot
+        .regions()
+        .filter(|x| x.region.region_id == name)
+        .collect();
+    match by_id.as_slice() {
+        [room] => return Ok(room.region.clone()),
+        [] => {}
+        _ => {
+            return Err(format!(
+                "Ambiguous id `{}`, use one of: {}",
+                name,
+                by_id
+                    .iter()
+                    .map(|x| format!("`{}`", x.name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        }
+    }
+
+    if !name.is_empty() && name.chars().all(|x| x.is_ascii_digit()) {
+        if !robot.zones.is_empty() {
+            return Err(format!(
+                "Unknown id `{}`: it can be a room or a zone, name it with `rooms learn` first",
+                name
+            ));
+        }
+        return Ok(api::Region::from(name));
+    }
+
+    let suggestions: Vec<_> = robot
+        .regions()
+        .filter(|x| {
+            let candidate = x.name.to_lowercase();
+            candidate.contains(&lowercase)
+                || distance(&candidate, &lowercase) <= std::cmp::max(2, lowercase.len() / 3)
+        })
+        .map(|x| format!("`{}`", x.name))
+        .collect();
+
+    if suggestions.is_empty() {
+        Err(format!("Unknown room or zone `{}`", name))
+    } else {
+        Err(format!(
+            "Unknown room or zone `{}`. Did you mean: {}?",
+            name,
+            suggestions.join(", ")
+        ))
+    }
+}
+
+/// Levenshtein distance between two strings.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, x) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous + if x == *y { 0 } else { 1 };
+            previous = row[j + 1];
+            row[j + 1] = std::cmp::min(substitution, std::cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Room;
+
+    fn region(id: &str, type_: &str) -> api::Region {
+        api::Region {
+            region_id: id.to_string(),
+            type_: type_.to_string(),
+        }
+    }
+
+    fn room(name: &str, id: &str, type_: &str) -> Room {
+        Room {
+            name: name.to_string(),
+            region: region(id, type_),
+        }
+    }
+
+    fn robot() -> Robot {
+        Robot {
+            rooms: vec![
+                room("Kitchen", "1", "rid"),
+                room("Salle à manger", "2", "rid"),
+            ],
+            zones: vec![room("Under the table", "1", "zid")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn region_by_name() {
+        let robot = robot();
+
+        assert_eq!(resolve_region("kitchen", &robot), Ok(region("1", "rid")));
+        assert_eq!(
+            resolve_region("SALLE À MANGER", &robot),
+            Ok(region("2", "rid"))
+        );
+        assert_eq!(
+            resolve_region("under the TABLE", &robot),
+            Ok(region("1", "zid"))
+        );
+    }
+
+    #[test]
+    fn ambiguous_name() {
+        let mut robot = robot();
+        robot.zones.push(room("KITCHEN", "3", "zid"));
+
+        assert_eq!(
+            resolve_region("kitchen", &robot),
+            Err(
+                "Ambiguous name `kitchen`, rename one of: room `Kitchen`, zone `KITCHEN`"
+                    .to_string()
+            )
+        );
+        assert_eq!(resolve_region("3", &robot), Ok(region("3", "zid")));
+    }
+
+    #[test]
+    fn region_by_id() {
+        let robot = robot();
+
+        assert_eq!(resolve_region("2", &robot), Ok(region("2", "rid")));
+        assert!(resolve_region("1", &robot)
+            .unwrap_err()
+            .starts_with("Ambiguous id `1`"));
+        assert!(resolve_region("7", &robot)
+            .unwrap_err()
+            .starts_with("Unknown id `7`"));
+    }
+
+    #[test]
+    fn raw_id_without_zones() {
+        let robot = Robot {
+            zones: vec![],
+            ..robot()
+        };
+
+        assert_eq!(resolve_region("7", &robot), Ok(region("7", "rid")));
+    }
+
+    #[test]
+    fn region_suggestions() {
+        let robot = robot();
+
+        assert_eq!(
+            resolve_region("kitchn", &robot),
+            Err("Unknown room or zone `kitchn`. Did you mean: `Kitchen`?".to_string())
+        );
+        assert_eq!(
+            resolve_region("table", &robot),
+            Err("Unknown room or zone `table`. Did you mean: `Under the table`?".to_string())
+        );
+        assert_eq!(
+            resolve_region("garage", &robot),
+            Err("Unknown room or zone `garage`".to_string())
+        );
+    }
+
+    #[test]
+    fn levenshtein_distance() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("kitchen", "kitchen"), 0);
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(distance("kitchen", "kitchn"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("à", "a"), 1);
+    }
 }
@@ -0,0 +1,604 @@
+use roomba::{api, MissionOutcome};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const ROOMBA_CFG: &str = "roomba.toml";
+pub const ROOMBA_SECRETS: &str = "roomba.secrets.toml";
+
+const DEFAULT_ROBOT: &str = "default";
+
+pub const ROOMBA_HOSTNAME: &str = "ROOMBA_HOSTNAME";
+pub const ROOMBA_BLID: &str = "ROOMBA_BLID";
+pub const ROOMBA_PASSWORD: &str = "ROOMBA_PASSWORD";
+
+#[derive(Serialize, Default, Debug)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_robot: Option<String>,
+    /// Path to the file holding the passwords, relative to the configuration file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets_file: Option<PathBuf>,
+    pub robots: BTreeMap<String, Robot>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+}
+
+/// A command run on an event of the robot.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Hook {
+    /// Type of the event (`stuck`, `error`, `mission_ended`, `battery`, `bin_full`, ...).
+    pub event: String,
+    /// Shell command to run.
+    pub command: String,
+    /// Only for this error code.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<u64>,
+    /// Only for the missions ended with this outcome.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<MissionOutcome>,
+    /// Only when the battery level goes below this level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below: Option<u64>,
+}
+
+/// An HTTP endpoint receiving the events of the robot.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Webhook {
+    pub url: String,
+    /// Types of the events sent. Every event if empty.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub events: Vec<String>,
+    /// Body of the request where `{{name}}` is replaced by the fields of the event and the status
+    /// of the robot. The event and the status as JSON if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Key of the HMAC-SHA256 signature of the body, sent in the `X-Roomba-Signature` header.
+    /// Stored in the secrets file by URL. Still read from the webhook for older configurations.
+    #[serde(skip_serializing)]
+    pub secret: Option<String>,
+    #[serde(default = "Webhook::default_retries")]
+    pub retries: u32,
+}
+
+impl Webhook {
+    fn default_retries() -> u32 {
+        3
+    }
+}
+
+/// The passwords of the robots by profile and the secrets of the webhooks by URL, kept out of the
+/// main configuration file.
+#[derive(Serialize, Deserialize, Default)]
+struct Secrets {
+    #[serde(default)]
+    passwords: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    webhooks: BTreeMap<String, String>,
+}
+
+/// The profile of a robot.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct Robot {
+    pub hostname: Option<String>,
+    #[serde(alias = "username")]
+    pub blid: Option<String>,
+    /// Stored in the secrets file. Still read from the profile for older configurations.
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    pub pmap_id: Option<String>,
+    pub user_pmapv_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub rooms: Vec<Room>,
+    /// Areas defined in the app (region type `zid`).
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub zones: Vec<Room>,
+    /// Named rooms and settings used by the schedules.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub presets: Vec<Preset>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub schedules: Vec<Schedule>,
+}
+
+impl Robot {
+    /// The rooms then the zones of the robot.
+    pub fn regions(&self) -> impl Iterator<Item = &Room> {
+        self.rooms.iter().chain(self.zones.iter())
+    }
+
+    /// Override the credentials of the profile with the environment variables `ROOMBA_HOSTNAME`,
+    /// `ROOMBA_BLID` and `ROOMBA_PASSWORD` when they are set.
+    pub fn with_env(mut self) -> Self {
+        let var = |name| std::env::var(name).ok().filter(|x: &String| !x.is_empty());
+
+        if let Some(hostname) = var(ROOMBA_HOSTNAME) {
+            self.hostname = Some(hostname);
+        }
+        if let Some(blid) = var(ROOMBA_BLID) {
+            self.blid = Some(blid);
+        }
+        if let Some(password) = var(ROOMBA_PASSWORD) {
+            self.password = Some(password);
+        }
+
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Room {
+    pub name: String,
+    #[serde(flatten)]
+    pub region: api::Region,
+}
+
+impl std::fmt::Display for Room {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Cleaning preferences applied before a job starts. The ones not set are left unchanged.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub passes: Option<api::Passes>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub carpet_boost: Option<api::CarpetBoost>,
+}
+
+impl Settings {
+    /// The settings of `self` with the missing ones taken from `other`.
+    pub fn or(&self, other: &Settings) -> Settings {
+        Settings {
+            passes: self.passes.or(other.passes),
+            carpet_boost: self.carpet_boost.or(other.carpet_boost),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Settings::default()
+    }
+}
+
+/// Rooms and settings of a cleaning job that can be used by several schedules.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    /// Names of the rooms and zones to clean. The whole home if empty.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub rooms: Vec<String>,
+    #[serde(default)]
+    pub ordered: bool,
+    #[serde(flatten)]
+    pub settings: Settings,
+}
+
+/// A cleaning job started by the daemon.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Schedule {
+    /// Local time of the day (`HH:MM`).
+    pub time: String,
+    /// Days of the week (`mon`, `tue`, ...). Every day if empty.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub days: Vec<String>,
+    /// Name of the preset giving the rooms and the settings of the job.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub preset: Option<String>,
+    /// Names of the rooms and zones to clean. The whole home if empty.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub rooms: Vec<String>,
+    #[serde(default)]
+    pub ordered: bool,
+    /// Override the settings of the preset.
+    #[serde(flatten)]
+    pub settings: Settings,
+    /// Skip the job if the battery is below this level (in percent).
+    #[serde(default = "Schedule::default_min_battery")]
+    pub min_battery: u64,
+}
+
+impl Schedule {
+    fn default_min_battery() -> u64 {
+        30
+    }
+}
+
+/// The configuration file as written before the profiles existed: the settings of the only
+/// robot were at the top level.
+#[derive(Deserialize)]
+struct RawConfig {
+    default_robot: Option<String>,
+    secrets_file: Option<PathBuf>,
+    #[serde(default)]
+    robots: BTreeMap<String, Robot>,
+    #[serde(default)]
+    hooks: Vec<Hook>,
+    #[serde(default)]
+    webhooks: Vec<Webhook>,
+    #[serde(flatten)]
+    legacy: Robot,
+}
+
+impl From<RawConfig> for Config {
+    fn from(raw: RawConfig) -> Self {
+        let mut config = Config {
+            default_robot: raw.default_robot,
+            secrets_file: raw.secrets_file,
+            robots: raw.robots,
+            hooks: raw.hooks,
+            webhooks: raw.webhooks,
+        };
+
+        if raw.legacy != Robot::default() {
+            config
+                .robots
+                .entry(DEFAULT_ROBOT.to_string())
+                .or_insert(raw.legacy);
+        }
+
+        config
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Self {
+        let mut config = if path.exists() {
+            std::fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|ref content| {
+                    toml::from_str::<RawConfig>(content).map_err(|err| err.to_string())
+                })
+                .map(Config::from)
+                .unwrap_or_else(|err| {
+                    eprintln!(
+                        "Could not read configuration file `{}`: {}",
+                        path.display(),
+                        err
+                    );
+                    Config::default()
+                })
+        } else {
+            Config::default()
+        };
+
+        let secrets_file = config.secrets_path(path);
+        if secrets_file.exists() {
+            let secrets = read_secrets(&secrets_file).unwrap_or_else(|err| {
+                eprintln!(
+                    "Could not read secrets file `{}`: {}",
+                    secrets_file.display(),
+                    err
+                );
+                Secrets::default()
+            });
+
+            // the secrets file wins over the secrets left in an older configuration
+            for (name, password) in secrets.passwords {
+                if let Some(robot) = config.robots.get_mut(&name) {
+                    robot.password = Some(password);
+                }
+            }
+            for webhook in config.webhooks.iter_mut() {
+                if let Some(secret) = secrets.webhooks.get(&webhook.url) {
+                    webhook.secret = Some(secret.clone());
+                }
+            }
+        }
+
+        config
+    }
+
+    /// Write the configuration and the secrets file, returns whether it succeeded. The errors are
+    /// printed.
+    pub fn save(&mut self, path: &Path) -> bool {
+        let passwords: BTreeMap<String, String> = self
+            .robots
+            .iter()
+            .filter_map(|(name, robot)| {
+                robot
+                    .password
+                    .clone()
+                    .map(|password| (name.clone(), password))
+            })
+            .collect();
+        let webhooks: BTreeMap<String, String> = self
+            .webhooks
+            .iter()
+            .filter_map(|x| x.secret.clone().map(|secret| (x.url.clone(), secret)))
+            .collect();
+
+        if !passwords.is_empty() || !webhooks.is_empty() {
+            if self.secrets_file.is_none() {
+                self.secrets_file = Some(PathBuf::from(ROOMBA_SECRETS));
+            }
+
+            // the secrets are not serialized in the configuration file: it must not be written if
+            // they could not be saved
+            let secrets_file = self.secrets_path(path);
+            let res = if secrets_file.exists() {
+                read_secrets(&secrets_file)
+            } else {
+                Ok(Secrets::default())
+            }
+            .and_then(|mut secrets| {
+                // keep the passwords of the robots that are not loaded
+                secrets.passwords.extend(passwords);
+                secrets.webhooks.extend(webhooks);
+                write_secrets(&secrets_file, &secrets)
+            });
+
+            if let Err(err) = res {
+                eprintln!(
+                    "Could not write secrets file `{}`: {}. The configuration has not been saved.",
+                    secrets_file.display(),
+                    err
+                );
+                return false;
+            }
+        }
+
+        toml::to_string_pretty(self)
+            .map_err(|err| err.to_string())
+            .and_then(|x| std::fs::write(path, x).map_err(|err| err.to_string()))
+            .map_err(|err| {
+                eprintln!(
+                    "Could not write configuration file `{}`: {}",
+                    path.display(),
+                    err
+                )
+            })
+            .is_ok()
+    }
+
+    fn secrets_path(&self, config_file: &Path) -> PathBuf {
+        let dir = config_file.parent().unwrap_or_else(|| Path::new(""));
+
+        dir.join(
+            self.secrets_file
+                .as_deref()
+                .unwrap_or_else(|| Path::new(ROOMBA_SECRETS)),
+        )
+    }
+
+    /// Name of the profile given on the command line, or of the default robot, or of the only
+    /// robot of the configuration.
+    pub fn selected(&self, name: Option<&str>) -> String {
+        name.map(String::from)
+            .or_else(|| self.default_robot.clone())
+            .or_else(|| {
+                if self.robots.len() == 1 {
+                    self.robots.keys().next().cloned()
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_else(|| DEFAULT_ROBOT.to_string())
+    }
+
+    pub fn robot(&self, name: &str) -> Option<&Robot> {
+        self.robots.get(name)
+    }
+
+    /// Get the profile of a robot, creating it if it doesn't exist. The first profile created
+    /// becomes the default robot.
+    pub fn robot_mut(&mut self, name: &str) -> &mut Robot {
+        if self.default_robot.is_none() && self.robots.is_empty() {
+            self.default_robot = Some(name.to_string());
+        }
+
+        self.robots.entry(name.to_string()).or_default()
+    }
+
+    pub fn find_by_blid(&self, blid: &str) -> Option<&str> {
+        self.robots
+            .iter()
+            .find(|(_, robot)| robot.blid.as_deref() == Some(blid))
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn find_by_hostname(&self, hostname: &str) -> Option<&str> {
+        self.robots
+            .iter()
+            .find(|(_, robot)| robot.hostname.as_deref() == Some(hostname))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// A name for a new profile based on the name of the robot that is not used yet.
+    pub fn new_profile_name(&self, robot_name: &str) -> String {
+        let base: String = robot_name
+            .chars()
+            .map(|x| {
+                if x.is_alphanumeric() {
+                    x.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        let base = if base.is_empty() {
+            "robot".to_string()
+        } else {
+            base
+        };
+
+        std::iter::once(base.clone())
+            .chain((2..).map(|i| format!("{}-{}", base, i)))
+            .find(|x| !self.robots.contains_key(x))
+            .unwrap()
+    }
+}
+
+fn read_secrets(path: &Path) -> Result<Secrets, String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(path)
+            .map_err(|err| err.to_string())?
+            .permissions()
+            .mode();
+        if mode & 0o007 != 0 {
+            return Err(format!(
+                "the file is readable by other users (mode {:o}), please run `chmod 600` on it",
+                mode & 0o777
+            ));
+        }
+    }
+
+    std::fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|ref content| toml::from_str(content).map_err(|err| err.to_string()))
+}
+
+fn write_secrets(path: &Path, secrets: &Secrets) -> Result<(), String> {
+    let content = toml::to_string_pretty(secrets).map_err(|err| err.to_string())?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+        // the mode is only applied when the file is created
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                .map_err(|err| err.to_string())?;
+        }
+    }
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY: &str = r#"
+hostname = "192.168.1.2"
+username = "0123456789ABCDEF"
+password = ":1:1598392010:7j28GmnS59cJTmPn"
+pmap_id = "map"
+user_pmapv_id = "200101T000000"
+
+[[rooms]]
+name = "Kitchen"
+region_id = "1"
+type = "rid"
+"#;
+
+    /// An empty directory of the system's temporary directory for the configuration files.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("roomba-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn legacy_robot() -> Robot {
+        Robot {
+            hostname: Some("192.168.1.2".to_string()),
+            blid: Some("0123456789ABCDEF".to_string()),
+            password: Some(":1:1598392010:7j28GmnS59cJTmPn".to_string()),
+            pmap_id: Some("map".to_string()),
+            user_pmapv_id: Some("200101T000000".to_string()),
+            rooms: vec![Room {
+                name: "Kitchen".to_string(),
+                region: api::Region::from("1"),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn load_legacy() {
+        let dir = temp_dir("load-legacy");
+        let path = dir.join(ROOMBA_CFG);
+        std::fs::write(&path, LEGACY).unwrap();
+
+        let config = Config::load(&path);
+
+        assert_eq!(config.robots.len(), 1);
+        assert_eq!(config.robot(DEFAULT_ROBOT), Some(&legacy_robot()));
+        assert_eq!(config.selected(None), DEFAULT_ROBOT);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_legacy() {
+        let dir = temp_dir("save-legacy");
+        let path = dir.join(ROOMBA_CFG);
+        std::fs::write(&path, LEGACY).unwrap();
+
+        let mut config = Config::load(&path);
+        assert!(config.save(&path));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("[robots.default]"));
+        assert!(!content.contains("password"));
+        assert!(dir.join(ROOMBA_SECRETS).exists());
+
+        let config = Config::load(&path);
+        assert_eq!(config.robots.len(), 1);
+        assert_eq!(config.robot(DEFAULT_ROBOT), Some(&legacy_robot()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn secrets_file_wins() {
+        let dir = temp_dir("secrets-file-wins");
+        let path = dir.join(ROOMBA_CFG);
+        std::fs::write(&path, LEGACY).unwrap();
+        let secrets = Secrets {
+            passwords: vec![(DEFAULT_ROBOT.to_string(), "new password".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        write_secrets(&dir.join(ROOMBA_SECRETS), &secrets).unwrap();
+
+        let config = Config::load(&path);
+
+        assert_eq!(
+            config.robot(DEFAULT_ROBOT).unwrap().password.as_deref(),
+            Some("new password")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn readable_secrets_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("readable-secrets-file");
+        let path = dir.join(ROOMBA_CFG);
+        let secrets_file = dir.join(ROOMBA_SECRETS);
+        std::fs::write(&path, "[robots.default]\nhostname = \"192.168.1.2\"\n").unwrap();
+        std::fs::write(&secrets_file, "[passwords]\ndefault = \"password\"\n").unwrap();
+        std::fs::set_permissions(&secrets_file, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        assert!(read_secrets(&secrets_file)
+            .err()
+            .unwrap()
+            .contains("chmod 600"));
+
+        let mut config = Config::load(&path);
+        assert_eq!(config.robot(DEFAULT_ROBOT).unwrap().password, None);
+
+        // the password would be lost
+        config.robot_mut(DEFAULT_ROBOT).password = Some("password".to_string());
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!config.save(&path));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
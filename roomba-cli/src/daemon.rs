@@ -0,0 +1,224 @@
+use crate::cli;
+use crate::config::{Robot, Schedule};
+use crate::hooks::Hooks;
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use futures::channel::mpsc;
+use futures::select;
+use futures::stream::StreamExt;
+use roomba::{api, Client, Event, MissionOutcome, State};
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+/// Interval between two checks of the schedules.
+const TICK: Duration = Duration::from_secs(10);
+
+struct Job {
+    time: NaiveTime,
+    days: Vec<Weekday>,
+    min_battery: u64,
+    message: api::Message,
+    /// Preferences sent before the command.
+    preferences: Option<api::Message>,
+    description: String,
+    last_run: Option<NaiveDate>,
+}
+
+impl Job {
+    fn new(schedule: &Schedule, robot: &Robot) -> Result<Self, String> {
+        let time = NaiveTime::parse_from_str(&schedule.time, "%H:%M")
+            .map_err(|err| format!("invalid time `{}`: {}", schedule.time, err))?;
+        let days = schedule
+            .days
+            .iter()
+            .map(|x| x.parse().map_err(|_| format!("invalid day `{}`", x)))
+            .collect::<Result<Vec<Weekday>, _>>()?;
+        let (rooms, ordered, settings) = match schedule.preset.as_ref() {
+            Some(_) if !schedule.rooms.is_empty() => {
+                return Err(format!(
+                    "the schedule at {} has both a preset and rooms",
+                    schedule.time
+                ))
+            }
+            Some(name) => {
+                let preset = robot
+                    .presets
+                    .iter()
+                    .find(|x| &x.name == name)
+                    .ok_or_else(|| format!("unknown preset `{}`", name))?;
+                (
+                    &preset.rooms,
+                    preset.ordered || schedule.ordered,
+                    schedule.settings.or(&preset.settings),
+                )
+            }
+            None => (&schedule.rooms, schedule.ordered, schedule.settings.clone()),
+        };
+        let command = if rooms.is_empty() {
+            cli::Command::Start
+        } else {
+            cli::Command::StartRegions {
+                ordered,
+                regions: rooms.clone(),
+            }
+        };
+        let (command, extra) = command.into_command_with_extra(robot)?;
+
+        Ok(Self {
+            time,
+            days,
+            min_battery: schedule.min_battery,
+            message: api::Message::new_command(command, extra),
+            preferences: if settings.is_empty() {
+                None
+            } else {
+                Some(api::Message::new_preferences(
+                    settings.passes,
+                    settings.carpet_boost,
+                ))
+            },
+            description: match (schedule.preset.as_ref(), rooms.is_empty()) {
+                (Some(name), _) => format!("{} {}", schedule.time, name),
+                (None, true) => format!("{} whole home", schedule.time),
+                (None, false) => format!("{} {}", schedule.time, rooms.join(", ")),
+            },
+            last_run: None,
+        })
+    }
+
+    fn is_due(&self, now: &NaiveDateTime) -> bool {
+        self.last_run != Some(now.date())
+            && (self.days.is_empty() || self.days.contains(&now.weekday()))
+            && now.time() >= self.time
+            && now.time() - self.time < chrono::Duration::minutes(1)
+    }
+}
+
+/// Start the cleaning jobs of the schedules of the robot on time, log their outcome and run the
+/// hooks of the events.
+pub async fn run(
+    client: &mut Client,
+    robot: &Robot,
+    hooks: &mut Hooks,
+) -> Result<(), Box<dyn Error>> {
+    let mut jobs = robot
+        .schedules
+        .iter()
+        .map(|x| Job::new(x, robot))
+        .collect::<Result<Vec<_>, _>>()?;
+    if jobs.is_empty() && hooks.is_empty() {
+        return Err("no schedule nor hook in the configuration".into());
+    }
+
+    for job in jobs.iter() {
+        log(&format!("scheduled: {}", job.description));
+    }
+
+    let mut state = State::default();
+    let mut ticks = ticks();
+
+    loop {
+        select! {
+            _ = ticks.next() => {
+                let now = Local::now().naive_local();
+                for job in jobs.iter_mut().filter(|x| x.is_due(&now)) {
+                    job.last_run = Some(now.date());
+                    start(client, &state, job).await;
+                }
+            },
+            message = client.events.next() => match message {
+                Some(Some(message)) => {
+                    let events = state.update(&message).unwrap_or_default();
+                    let status = state.status();
+                    for event in events.iter() {
+                        log_event(event);
+                        hooks.handle(event, &status);
+                    }
+                }
+                Some(None) => log("connection to the robot lost"),
+                None => break,
+            },
+            connection = client.states.next() => {
+                if let Some(connection) = connection {
+                    log(&format!("connection {}", connection));
+                }
+            },
+            complete => break,
+        }
+    }
+
+    Err("the connection to the robot has been closed".into())
+}
+
+async fn start(client: &mut Client, state: &State, job: &Job) {
+    let status = state.status();
+
+    if !state.is_synced() {
+        log(&format!(
+            "skipped {}: the state of the robot is unknown",
+            job.description
+        ));
+    } else if status.cycle.as_deref().unwrap_or("none") != "none" {
+        log(&format!("skipped {}: the robot is busy", job.description));
+    } else if status.battery.unwrap_or(0) < job.min_battery {
+        log(&format!(
+            "skipped {}: battery too low ({}%)",
+            job.description,
+            status.battery.unwrap_or(0)
+        ));
+    } else {
+        if let Some(preferences) = job.preferences.as_ref() {
+            if let Err(err) = client.send_message(preferences).await {
+                log(&format!(
+                    "could not change the settings for {}: {}",
+                    job.description, err
+                ));
+                return;
+            }
+        }
+
+        match client.send_message(&job.message).await {
+            Ok(()) => log(&format!("started {}", job.description)),
+            Err(err) => log(&format!("could not start {}: {}", job.description, err)),
+        }
+    }
+}
+
+fn log_event(event: &Event) {
+    match event {
+        Event::MissionStarted { cycle } => log(&format!("mission started: {}", cycle)),
+        Event::MissionEnded {
+            outcome,
+            minutes,
+            area,
+        } => log(&format!(
+            "mission {}: {} min, {} ft²",
+            match outcome {
+                MissionOutcome::Completed => "completed",
+                MissionOutcome::Cancelled => "cancelled",
+            },
+            minutes.unwrap_or(0),
+            area.unwrap_or(0)
+        )),
+        Event::Stuck => log("the robot is stuck"),
+        Event::Error { code } => log(&format!("the robot is in error: {}", code)),
+        Event::BinFull => log("the bin is full"),
+        _ => {}
+    }
+}
+
+fn log(message: &str) {
+    println!("{} {}", Local::now().format("%Y-%m-%d %H:%M:%S"), message);
+}
+
+fn ticks() -> mpsc::UnboundedReceiver<()> {
+    let (tx, rx) = mpsc::unbounded();
+    thread::spawn(move || loop {
+        thread::sleep(TICK);
+        if tx.unbounded_send(()).is_err() {
+            break;
+        }
+    });
+
+    rx
+}
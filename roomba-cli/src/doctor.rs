@@ -0,0 +1,284 @@
+use crate::config::Robot;
+use futures::stream::StreamExt;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use roomba::{Client, Discovery, State};
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Exit code when one of the checks failed.
+pub const EXIT_FAILED: i32 = 1;
+
+const PORT: u16 = 8883;
+/// Time given to the network checks.
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const FIX_SESSION: &str = "the robot accepts only one local connection at a time: stop the other \
+    client (another instance of this program, a home automation integration...) or share the \
+    connection with the `proxy` command";
+
+fn ok(check: &str, detail: &str) {
+    println!("[ok]      {}: {}", check, detail);
+}
+
+fn warning(check: &str, diagnosis: &str, fix: &str) {
+    println!("[warning] {}: {}", check, diagnosis);
+    println!("          fix: {}", fix);
+}
+
+fn failed(check: &str, diagnosis: &str, fix: &str) -> i32 {
+    println!("[failed]  {}: {}", check, diagnosis);
+    println!("          fix: {}", fix);
+    EXIT_FAILED
+}
+
+/// Check step by step what is needed to control the robot and explain how to fix what fails.
+pub async fn run(name: &str, robot: &Robot, timeout: u64) -> Result<i32, Box<dyn Error>> {
+    println!("Checking robot: {}", name);
+
+    let blid = match robot.blid.as_ref() {
+        Some(blid) => blid,
+        None => {
+            return Ok(failed(
+                "configuration",
+                "the BLID of the robot is missing",
+                "run `find-ip` or `setup`",
+            ))
+        }
+    };
+    match (robot.hostname.as_ref(), robot.password.as_ref()) {
+        (Some(_), Some(_)) => ok("configuration", "hostname, BLID and password are set"),
+        (None, _) => warning(
+            "configuration",
+            "the hostname of the robot is missing",
+            "run `find-ip` or `setup`",
+        ),
+        (_, None) => warning(
+            "configuration",
+            "the password of the robot is missing",
+            "run `get-password` or `setup`",
+        ),
+    }
+
+    let hostname = match Discovery::find(blid, NETWORK_TIMEOUT) {
+        Ok(Some(info)) if robot.hostname.as_ref() == Some(&info.ip) => {
+            ok("discovery", &format!("the robot answered from {}", info.ip));
+            info.ip
+        }
+        Ok(Some(info)) => {
+            warning(
+                "discovery",
+                &format!(
+                    "the robot answered from {} but the configuration has {}",
+                    info.ip,
+                    robot.hostname.as_deref().unwrap_or("no address")
+                ),
+                "run `find-ip` to save the new address (it is also updated when connecting)",
+            );
+            info.ip
+        }
+        res => {
+            match res {
+                Err(err) if err.kind() == ErrorKind::AddrInUse => warning(
+                    "discovery",
+                    "the UDP port 5678 is used by another program",
+                    "stop the other program to use discovery",
+                ),
+                Err(err) => warning(
+                    "discovery",
+                    &format!("could not send the discovery broadcast: {}", err),
+                    "check the network configuration of this computer",
+                ),
+                _ => warning(
+                    "discovery",
+                    "the robot did not answer the discovery broadcast on UDP port 5678",
+                    "check that this computer is on the same network as the robot and that \
+                    broadcasts are not blocked by a firewall or by the client isolation of the \
+                    Wi-Fi access point",
+                ),
+            }
+
+            match robot.hostname.clone() {
+                Some(hostname) => hostname,
+                None => {
+                    return Ok(failed(
+                        "network",
+                        "the address of the robot is unknown",
+                        "run `find-ip` when the robot is online",
+                    ))
+                }
+            }
+        }
+    };
+
+    let addr = match (hostname.as_str(), PORT)
+        .to_socket_addrs()
+        .map(|mut x| x.next())
+    {
+        Ok(Some(addr)) => addr,
+        Ok(None) | Err(_) => {
+            return Ok(failed(
+                "network",
+                &format!("could not resolve {}", hostname),
+                "check the hostname in the configuration or run `find-ip`",
+            ))
+        }
+    };
+    let socket = match TcpStream::connect_timeout(&addr, NETWORK_TIMEOUT) {
+        Ok(socket) => {
+            ok("network", &format!("{} accepts connections", addr));
+            socket
+        }
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+            return Ok(failed(
+                "network",
+                &format!("{} refused the connection", addr),
+                "this is probably not the address of the robot or the robot is restarting: run \
+                `find-ip` to check its address",
+            ))
+        }
+        Err(err) if err.kind() == ErrorKind::TimedOut || err.kind() == ErrorKind::WouldBlock => {
+            return Ok(failed(
+                "network",
+                &format!("{} did not answer", addr),
+                "check that the robot is on and connected to the Wi-Fi (it should be online in \
+                the iRobot app)",
+            ))
+        }
+        Err(err) => {
+            return Ok(failed(
+                "network",
+                &format!("could not connect to {}: {}", addr, err),
+                "check that this computer can reach the network of the robot",
+            ))
+        }
+    };
+
+    // same constraints as the MQTT connection: self-signed certificate and no DH key exchange
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    builder.set_verify(SslVerifyMode::NONE);
+    if let Err(err) = builder.set_cipher_list("DEFAULT:!DH") {
+        return Ok(failed(
+            "tls",
+            &format!("the ciphers of the robot are not available: {}", err),
+            "use a build of OpenSSL supporting the legacy ciphers",
+        ));
+    }
+    socket.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+    match builder.build().connect("ignore", socket) {
+        Ok(stream) => ok(
+            "tls",
+            &format!(
+                "handshake succeeded ({}, {})",
+                stream.ssl().version_str(),
+                stream
+                    .ssl()
+                    .current_cipher()
+                    .map(|x| x.name())
+                    .unwrap_or("unknown cipher")
+            ),
+        ),
+        Err(err) => {
+            return Ok(failed(
+                "tls",
+                &format!("the handshake failed: {}", err),
+                "the robot only supports old ciphers: lower the security level of OpenSSL (e.g. \
+                `CipherString = DEFAULT@SECLEVEL=1` in openssl.cnf)",
+            ))
+        }
+    }
+
+    let password = match robot.password.as_ref() {
+        Some(password) => password,
+        None => {
+            return Ok(failed(
+                "authentication",
+                "the password of the robot is missing",
+                "run `get-password` or `setup`",
+            ))
+        }
+    };
+    let timeout = Duration::from_secs(timeout);
+    let mut client = match async_std::future::timeout(
+        timeout,
+        Client::new(&hostname, blid.as_str(), password.as_str(), 0),
+    )
+    .await
+    {
+        Ok(Ok(client)) => {
+            ok("authentication", "the BLID and password were accepted");
+            client
+        }
+        Ok(Err(err)) => {
+            return Ok(if roomba::is_refused(&err) {
+                failed(
+                    "authentication",
+                    &format!("the robot refused the BLID or the password: {}", err),
+                    "run `setup` (or `find-ip` and `get-password`) to get them again",
+                )
+            } else {
+                failed(
+                    "authentication",
+                    &format!("the MQTT connection failed: {}", err),
+                    FIX_SESSION,
+                )
+            });
+        }
+        Err(_) => {
+            return Ok(failed(
+                "authentication",
+                "the robot did not answer the MQTT connection",
+                FIX_SESSION,
+            ))
+        }
+    };
+
+    let mut state = State::default();
+    let res = async_std::future::timeout(timeout, async {
+        while let Some(message) = client.events.next().await {
+            match message {
+                Some(message) => {
+                    state.update(&message);
+                    if state.is_synced() {
+                        return true;
+                    }
+                }
+                None => return false,
+            }
+        }
+
+        false
+    })
+    .await;
+
+    Ok(match res {
+        Ok(true) => {
+            let status = state.status();
+            ok("session", "the connection was kept");
+            ok(
+                "state",
+                &format!(
+                    "received (battery: {}%, phase: {})",
+                    status.battery.unwrap_or(0),
+                    status.phase.as_deref().unwrap_or("unknown")
+                ),
+            );
+            0
+        }
+        Ok(false) => failed(
+            "session",
+            "the robot closed the connection right after the authentication",
+            FIX_SESSION,
+        ),
+        Err(_) => {
+            ok("session", "the connection was kept");
+            failed(
+                "state",
+                &format!("the robot did not send its state in {}s", timeout.as_secs()),
+                "wake up the robot (press a button or open the iRobot app) and try again, restart \
+                the robot if it persists",
+            )
+        }
+    })
+}
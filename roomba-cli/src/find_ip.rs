@@ -0,0 +1,150 @@
+use crate::config::Config;
+use roomba::api::Info;
+use roomba::Discovery;
+use serde_json::json;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+const UNKNOWN: &str = "-";
+
+/// Look for the robots on the network, list them and save the one picked by the user.
+pub fn run(
+    config: &mut Config,
+    config_file: &Path,
+    profile: Option<String>,
+    timeout: u64,
+    json: bool,
+    no_save: bool,
+) -> Result<(), Box<dyn Error>> {
+    if !json {
+        eprintln!("Looking for robots for {} seconds...", timeout);
+    }
+    let robots = Discovery::find_all(Duration::from_secs(timeout))?;
+
+    if json {
+        let robots = robots
+            .iter()
+            .map(|x| {
+                json!({
+                    "ip": x.ip,
+                    "hostname": x.hostname,
+                    "blid": x.robot_id().ok(),
+                    "name": x.name(),
+                    "model": x.model(),
+                    "firmware": x.firmware(),
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&robots)?);
+        return Ok(());
+    }
+
+    if robots.is_empty() {
+        return Err("no robot found on the network".into());
+    }
+
+    print_table(&robots);
+
+    if no_save {
+        return Ok(());
+    }
+
+    let info = if robots.len() == 1 {
+        &robots[0]
+    } else {
+        match choose("Robot to save", robots.len())? {
+            Some(i) => &robots[i],
+            None => return Ok(()),
+        }
+    };
+
+    let blid = info
+        .robot_id()
+        .map_err(|err| format!("cannot save the robot at {}: {}", info.ip, err))?;
+    let name = profile_name(config, info, &blid, profile);
+    let robot = config.robot_mut(&name);
+    robot.hostname = Some(info.ip.clone());
+    robot.blid = Some(blid);
+    if config.save(config_file) {
+        println!("Saved as robot: {}", name);
+    }
+
+    Ok(())
+}
+
+/// Name of the profile of a robot: the one having its BLID, the one given by the user or a new one
+/// named after the robot.
+pub fn profile_name(config: &Config, info: &Info, blid: &str, profile: Option<String>) -> String {
+    config
+        .find_by_blid(blid)
+        .map(String::from)
+        .or(profile)
+        .unwrap_or_else(|| config.new_profile_name(info.name().unwrap_or(&info.hostname)))
+}
+
+pub fn print_table(robots: &[Info]) {
+    let rows = robots
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            vec![
+                (i + 1).to_string(),
+                x.ip.clone(),
+                x.robot_id().unwrap_or_else(|_| UNKNOWN.to_string()),
+                x.name().unwrap_or(UNKNOWN).to_string(),
+                x.model().unwrap_or(UNKNOWN).to_string(),
+                x.firmware().unwrap_or(UNKNOWN).to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = ["#", "IP", "BLID", "NAME", "MODEL", "FIRMWARE"];
+    let widths = header
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(std::iter::once(x.len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let print_row = |cells: &[&str]| {
+        let line = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(&header);
+    for row in rows.iter() {
+        print_row(&row.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+}
+
+/// Ask the user to pick one of the `count` items listed, `None` if the user skipped.
+pub fn choose(question: &str, count: usize) -> std::io::Result<Option<usize>> {
+    loop {
+        let mut line = String::new();
+
+        print!("{} (1-{}, leave empty to skip): ", question, count);
+        std::io::stdout().flush()?;
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        match line.trim() {
+            "" => return Ok(None),
+            x => match x.parse::<usize>() {
+                Ok(i) if (1..=count).contains(&i) => return Ok(Some(i - 1)),
+                _ => eprintln!("Invalid choice: {}", x),
+            },
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use crate::config::{Hook, Webhook};
+use crate::webhooks;
+use roomba::{Event, Status};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Run the commands of the hooks and call the webhooks matching the events of the robot.
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    webhooks: Vec<Webhook>,
+    robot: String,
+    battery: Option<u64>,
+}
+
+impl Hooks {
+    /// Refuse the hooks and the webhooks with an unknown event.
+    pub fn new(
+        hooks: Vec<Hook>,
+        webhooks: Vec<Webhook>,
+        robot: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let names = hooks
+            .iter()
+            .map(|x| &x.event)
+            .chain(webhooks.iter().flat_map(|x| x.events.iter()));
+        for name in names {
+            if !Event::NAMES.contains(&name.as_str()) {
+                return Err(format!(
+                    "Unknown event `{}` in the hooks, use one of: {}",
+                    name,
+                    Event::NAMES.join(", ")
+                )
+                .into());
+            }
+        }
+
+        Ok(Self {
+            hooks,
+            webhooks,
+            robot: robot.to_string(),
+            battery: None,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty() && self.webhooks.is_empty()
+    }
+
+    pub fn handle(&mut self, event: &Event, status: &Status) {
+        let previous_battery = match event {
+            Event::Battery { level } => self.battery.replace(*level),
+            _ => None,
+        };
+        let event = serde_json::to_value(event).expect("serialization failed");
+        let name = event["type"].as_str().unwrap_or_default();
+
+        let payload = json!({
+            "robot": self.robot,
+            "event": event,
+            "status": status,
+        });
+
+        for hook in self.hooks.iter().filter(|x| x.event == name) {
+            if hook.matches(&event, previous_battery) {
+                run(hook, &event, &payload);
+            }
+        }
+
+        for webhook in self.webhooks.iter() {
+            if webhook.events.is_empty() || webhook.events.iter().any(|x| x == name) {
+                webhooks::deliver(webhook, &payload);
+            }
+        }
+    }
+}
+
+impl Hook {
+    fn matches(&self, event: &Value, previous_battery: Option<u64>) -> bool {
+        if let Some(code) = self.code {
+            if event["code"].as_u64() != Some(code) {
+                return false;
+            }
+        }
+
+        if let Some(outcome) = self.outcome {
+            if event["outcome"] != json!(outcome) {
+                return false;
+            }
+        }
+
+        if let Some(below) = self.below {
+            // only when the level goes under the threshold
+            let level = event["level"].as_u64().unwrap_or(u64::MAX);
+            if level >= below || previous_battery.unwrap_or(u64::MAX) < below {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Run the command of a hook with the details of the event in the environment and as JSON on
+/// stdin.
+fn run(hook: &Hook, event: &Value, payload: &Value) {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&hook.command)
+        .env(
+            "ROOMBA_ROBOT",
+            payload["robot"].as_str().unwrap_or_default(),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null());
+
+    if let Some(fields) = event.as_object() {
+        for (key, value) in fields {
+            let key = if key == "type" { "event" } else { key };
+            let value = match value {
+                Value::String(x) => x.clone(),
+                Value::Null => String::new(),
+                x => x.to_string(),
+            };
+            command.env(format!("ROOMBA_{}", key.to_uppercase()), value);
+        }
+    }
+
+    let input = payload.to_string();
+    let description = hook.command.clone();
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            warn!("could not run hook `{}`: {}", description, err);
+            return;
+        }
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // the command may not read its input
+        let _ = stdin.write_all(input.as_bytes());
+    }
+
+    thread::spawn(move || match child.wait() {
+        Ok(status) if status.success() => debug!("hook `{}` succeeded", description),
+        Ok(status) => warn!("hook `{}` failed: {}", description, status),
+        Err(err) => warn!("hook `{}` failed: {}", description, err),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(event: &str) -> Hook {
+        Hook {
+            event: event.to_string(),
+            command: "true".to_string(),
+            code: None,
+            outcome: None,
+            below: None,
+        }
+    }
+
+    fn webhook(events: &[&str]) -> Webhook {
+        Webhook {
+            url: "http://localhost/hook".to_string(),
+            events: events.iter().map(|x| x.to_string()).collect(),
+            template: None,
+            secret: None,
+            retries: 0,
+        }
+    }
+
+    #[test]
+    fn known_events() {
+        let hooks = vec![hook("stuck"), hook("mission_ended")];
+        let webhooks = vec![webhook(&[]), webhook(&["bin_full", "battery"])];
+
+        assert!(Hooks::new(hooks, webhooks, "default").is_ok());
+    }
+
+    #[test]
+    fn unknown_hook_event() {
+        let err = Hooks::new(vec![hook("mission_end")], vec![], "default")
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().contains("`mission_end`"));
+        assert!(err.to_string().contains("mission_ended"));
+    }
+
+    #[test]
+    fn unknown_webhook_event() {
+        let webhooks = vec![webhook(&["docked", "Stuck"])];
+
+        assert!(Hooks::new(vec![], webhooks, "default").is_err());
+    }
+}
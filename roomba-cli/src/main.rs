@@ -1,38 +1,31 @@
+#[macro_use]
+extern crate log;
+
+mod bridge;
 mod cli;
+mod config;
+mod daemon;
+mod doctor;
+mod find_ip;
+mod hooks;
+mod metrics;
+mod mission;
+mod proxy;
+mod raw;
+mod rooms;
+mod serve;
+mod setup;
+mod status;
 mod tui;
+mod watch;
+mod webhooks;
 
 use async_std::task::block_on;
-use roomba::{api, Client};
-use serde::{Deserialize, Serialize};
-use std::io::Write;
+use config::{Config, ROOMBA_CFG};
+use roomba::{api, Client, Fleet, Watchdog};
+use std::path::Path;
 use structopt::StructOpt;
 
-const ROOMBA_CFG: &str = "roomba.toml";
-
-#[derive(Serialize, Deserialize, Default)]
-struct Config {
-    hostname: Option<String>,
-    username: Option<String>,
-    password: Option<String>,
-    pmap_id: Option<String>,
-    user_pmapv_id: Option<String>,
-    #[serde(skip_serializing_if = "Vec::is_empty", default)]
-    rooms: Vec<Room>,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Room {
-    name: String,
-    #[serde(flatten)]
-    region: api::Region,
-}
-
-impl std::fmt::Display for Room {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.name)
-    }
-}
-
 macro_rules! unwrap {
     ($option:expr, $message:expr) => {{
         if $option.is_none() {
@@ -53,72 +46,47 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Default to "error" log level unless overridden by environment
     env_logger::init_from_env(env_logger::Env::default().filter_or("RUST_LOG", "error"));
 
-    let config_file = dirs::config_dir().unwrap_or_default().join(ROOMBA_CFG);
-    let mut config = if config_file.exists() {
-        std::fs::read_to_string(&config_file)
-            .map_err(|err| err.to_string())
-            .and_then(|ref content| toml::from_str(content).map_err(|err| err.to_string()))
-            .unwrap_or_else(|err| {
-                eprintln!(
-                    "Could not read configuration file `{}`: {}",
-                    config_file.display(),
-                    err
-                );
-                Config::default()
-            })
-    } else {
-        Config::default()
-    };
-    let save_config = |config| {
-        toml::to_string_pretty(&config)
-            .map_err(|err| err.to_string())
-            .and_then(|x| std::fs::write(&config_file, x).map_err(|err| err.to_string()))
-            .unwrap_or_else(|err| {
-                eprintln!(
-                    "Could not write configuration file `{}`: {}",
-                    config_file.display(),
-                    err
-                )
-            })
-    };
-    let cli = cli::Cli::from_args();
-
-    match cli.command {
-        cli::AnyCommand::Unauthenticated(cli::UnauthenticatedCommand::FindIp { no_save }) => {
-            let stdout = std::io::stdout();
-
-            for res in Client::find_ip_address()? {
-                let mut fh = stdout.lock();
-                if let Ok(info) = res {
-                    let _ = writeln!(
-                        fh,
-                        "found.\nIP address: {}\nBLID/Username/Robot ID: {}",
-                        info.ip,
-                        info.robot_id()
-                            .unwrap_or_else(|err| panic!("{}: {:?}", err, info)),
-                    );
-                    if !no_save {
-                        config.hostname = Some(info.ip.clone());
-                        config.username = info.robot_id().ok();
-                        save_config(config);
-                        break;
-                    }
-                } else {
-                    let _ = fh.write(b".");
-                }
-                let _ = fh.flush();
-            }
+    let cli::Cli {
+        config: config_file,
+        robot,
+        session,
+        command,
+    } = cli::Cli::from_args();
+    let config_file =
+        config_file.unwrap_or_else(|| dirs::config_dir().unwrap_or_default().join(ROOMBA_CFG));
+    let mut config = Config::load(&config_file);
 
-            Ok(())
-        }
+    match command {
+        cli::AnyCommand::Unauthenticated(cli::UnauthenticatedCommand::FindIp {
+            no_save,
+            json,
+            timeout,
+        }) => find_ip::run(&mut config, &config_file, robot, timeout, json, no_save),
         cli::AnyCommand::Unauthenticated(cli::UnauthenticatedCommand::GetPassword {
             hostname,
             no_save,
         }) => {
+            // a new address is a new robot: never overwrite the default profile with it
+            let name = match (robot.clone(), hostname.as_ref()) {
+                (Some(name), _) => name,
+                (None, Some(hostname)) => config
+                    .find_by_hostname(hostname)
+                    .map(String::from)
+                    .unwrap_or_else(|| config.new_profile_name(hostname)),
+                (None, None) => config.selected(None),
+            };
+            // the address given by ROOMBA_HOSTNAME is not the one of the profile
+            let profile_hostname = config.robot(&name).and_then(|x| x.hostname.clone());
+            let from_argument = hostname.is_some();
             let hostname = match hostname {
-                Some(ref x) => x,
+                Some(x) => x,
                 None => unwrap!(
-                    config.hostname.as_ref(),
+                    config
+                        .robot(&name)
+                        .cloned()
+                        .unwrap_or_default()
+                        .with_env()
+                        .hostname,
                     "Missing hostname in the configuration. Please run `{exe} find-ip` first"
                 ),
             };
@@ -129,7 +97,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             );
 
             let password = loop {
-                match Client::get_password(hostname) {
+                match Client::get_password(&hostname) {
                     Err(err) => {
                         println!("{}", err);
                         std::thread::sleep(std::time::Duration::from_secs(3));
@@ -141,63 +109,138 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Password: {}", password);
 
             if !no_save {
-                config.hostname = Some(hostname.to_string());
-                config.password = Some(password);
-                save_config(config);
+                let robot = config.robot_mut(&name);
+                if from_argument || profile_hostname.as_ref() == Some(&hostname) {
+                    robot.hostname = Some(hostname);
+                }
+                robot.password = Some(password);
+                let missing_blid = robot.blid.is_none();
+                if config.save(&config_file) {
+                    println!("Saved as robot: {}", name);
+                }
+                if missing_blid {
+                    println!("The BLID of this robot is unknown, run `find-ip` to save it.");
+                }
             }
 
             Ok(())
         }
-        cli::AnyCommand::Authenticated(cli) => block_on(async {
-            let mut client = Client::new(
-                unwrap!(
-                    config.hostname.as_ref(),
-                    "Missing hostname in the configuration. Please run `{exe} find-ip` first"
-                ),
-                unwrap!(
-                    config.username.as_ref(),
-                    "Missing username in the configuration. Please run `{exe} find-ip` first"
-                ),
-                unwrap!(
-                    config.password.as_ref(),
-                    "Missing password in the configuration. Please run `{exe} get-password` first"
-                ),
-                0,
+        cli::AnyCommand::Unauthenticated(cli::UnauthenticatedCommand::Doctor { timeout }) => {
+            let name = config.selected(robot.as_deref());
+            let robot = config.robot(&name).cloned().unwrap_or_default().with_env();
+            let code = block_on(doctor::run(&name, &robot, timeout))?;
+            std::process::exit(code);
+        }
+        cli::AnyCommand::Unauthenticated(cli::UnauthenticatedCommand::Setup { timeout }) => {
+            block_on(setup::run(&mut config, &config_file, robot, timeout))
+        }
+        cli::AnyCommand::Authenticated(command) if command.all => block_on(async {
+            if command.wait {
+                eprintln!("--wait can not be used with --all");
+                std::process::exit(1);
+            }
+            let command = unwrap!(
+                command.command,
+                "Missing command to send to all the robots. Please run `{exe} command --help`"
+            );
+            if let cli::Command::StartRegions { .. } = command {
+                eprintln!("start-regions can not be sent to all the robots");
+                std::process::exit(1);
+            }
+            let (command, extra) = command.into_command_with_extra(&Default::default())?;
+            let message = api::Message::new_command(command, extra);
+
+            let mut fleet = Fleet::new();
+            for (name, robot) in config.robots.iter() {
+                let (hostname, blid, password) = match (
+                    robot.hostname.as_ref(),
+                    robot.blid.as_ref(),
+                    robot.password.as_ref(),
+                ) {
+                    (Some(hostname), Some(blid), Some(password)) => (hostname, blid, password),
+                    _ => {
+                        eprintln!("{}: incomplete configuration, skipped", name);
+                        continue;
+                    }
+                };
+
+                if let Err(err) = fleet.connect(hostname, blid, password, 0).await {
+                    eprintln!("{}: could not connect: {}", name, err);
+                }
+            }
+
+            for (blid, res) in fleet.broadcast(&message).await {
+                let name = config.find_by_blid(&blid).unwrap_or(&blid);
+                match res {
+                    Ok(()) => println!("{}: sent", name),
+                    Err(err) => eprintln!("{}: {}", name, err),
+                }
+            }
+
+            Ok(())
+        }),
+        cli::AnyCommand::Authenticated(command) => block_on(async {
+            let name = config.selected(robot.as_deref());
+            let mut client = connect(
+                &session,
+                session.watchdog(),
+                &mut config,
+                &name,
+                &config_file,
             )
             .await?;
+            let robot = config.robot_mut(&name);
 
-            match cli.command {
-                Some(command) => {
-                    let pmap_id = unwrap!(
-                        config.pmap_id.as_ref(),
-                        "Missing pmap_id in the configuration. Please run `{exe} TODO` first"
-                    );
-                    let user_pmapv_id = unwrap!(
-                        config.user_pmapv_id.as_ref(),
-                        "Missing user_pmapv_id in the configuration. Please run `{exe} TODO` \
-                        first"
-                    );
+            let cli::AuthenticatedCommand {
+                wait,
+                timeout,
+                command,
+                ..
+            } = command;
 
-                    let (command, extra) = command.into_command_with_extra(pmap_id, user_pmapv_id);
+            match command {
+                Some(ref command) if wait && !command.is_mission() => {
+                    eprintln!("--wait is only supported by the commands starting a mission");
+                    std::process::exit(1);
+                }
+                Some(command) => {
+                    let (command, extra) =
+                        command
+                            .into_command_with_extra(robot)
+                            .unwrap_or_else(|err| {
+                                eprintln!("{}", err);
+                                std::process::exit(1);
+                            });
                     let message = api::Message::new_command(command, extra);
 
                     client.send_message(&message).await?;
+
+                    if wait {
+                        let code = mission::wait(&mut client, timeout, session.reconnect).await?;
+                        std::process::exit(code);
+                    }
                 }
                 None => {
-                    let map = config
+                    let map = robot
                         .pmap_id
                         .clone()
-                        .and_then(|x| config.user_pmapv_id.clone().map(|y| (x, y)));
-                    let save = map.is_none();
+                        .and_then(|x| robot.user_pmapv_id.clone().map(|y| (x, y)));
+                    // the map of a recording is not necessarily the one of the profile
+                    let save = map.is_none() && session.replay.is_none();
 
-                    let app = tui::App::new(&mut client, config.rooms.as_slice(), map);
+                    let app = tui::App::new(
+                        &mut client,
+                        robot.rooms.as_slice(),
+                        robot.zones.as_slice(),
+                        map,
+                    );
                     let map = app.main_loop().await?;
 
                     if save {
                         if let Some((pmap_id, user_pmapv_id)) = map {
-                            config.pmap_id = Some(pmap_id);
-                            config.user_pmapv_id = Some(user_pmapv_id);
-                            save_config(config);
+                            robot.pmap_id = Some(pmap_id);
+                            robot.user_pmapv_id = Some(user_pmapv_id);
+                            config.save(&config_file);
                         }
                     }
                 }
@@ -205,5 +248,130 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             Ok(())
         }),
+        cli::AnyCommand::Connected(command) => block_on(async {
+            let name = config.selected(robot.as_deref());
+            let watchdog = session.watchdog().or_else(|| command.default_watchdog());
+            let mut client = connect(&session, watchdog, &mut config, &name, &config_file).await?;
+
+            match command {
+                cli::ConnectedCommand::Proxy {
+                    listen,
+                    tls_cert,
+                    tls_key,
+                    username,
+                    password,
+                } => {
+                    let tls = match (tls_cert.as_ref(), tls_key.as_ref()) {
+                        (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+                        (None, None) => None,
+                        _ => return Err("both --tls-cert and --tls-key are required".into()),
+                    };
+                    let credentials = match (username.as_ref(), password.as_ref()) {
+                        (Some(username), Some(password)) => {
+                            Some((username.as_str(), password.as_str()))
+                        }
+                        (None, None) => None,
+                        _ => return Err("both --username and --password are required".into()),
+                    };
+
+                    proxy::run(&mut client, &listen, tls, credentials).await
+                }
+                cli::ConnectedCommand::Status { json, timeout } => {
+                    let code = status::run(&mut client, json, timeout).await?;
+                    std::process::exit(code);
+                }
+                cli::ConnectedCommand::Raw(cli::RawCommand::Publish { topic, payload }) => {
+                    raw::publish(&client, &topic, &payload).await
+                }
+                cli::ConnectedCommand::Dump { timeout } => raw::dump(&mut client, timeout).await,
+                cli::ConnectedCommand::Rooms(cli::RoomsCommand::Learn) => {
+                    rooms::learn(&mut client, &mut config, &name, &config_file).await
+                }
+                cli::ConnectedCommand::Serve { listen } => {
+                    let robot = config.robot(&name).cloned().unwrap_or_default();
+                    serve::run(&mut client, &robot, &listen).await
+                }
+                cli::ConnectedCommand::Metrics { listen } => {
+                    metrics::run(&mut client, &listen).await
+                }
+                cli::ConnectedCommand::Bridge(options) => {
+                    let robot = config.robot(&name).cloned().unwrap_or_default();
+                    bridge::run(&mut client, &name, &robot, &options).await
+                }
+                cli::ConnectedCommand::Daemon => {
+                    let robot = config.robot(&name).cloned().unwrap_or_default();
+                    let mut hooks =
+                        hooks::Hooks::new(config.hooks.clone(), config.webhooks.clone(), &name)?;
+                    daemon::run(&mut client, &robot, &mut hooks).await
+                }
+                cli::ConnectedCommand::Watch { events } => {
+                    let mut hooks =
+                        hooks::Hooks::new(config.hooks.clone(), config.webhooks.clone(), &name)?;
+                    watch::run(&mut client, events, &mut hooks).await
+                }
+            }
+        }),
     }
 }
+
+async fn connect(
+    session: &cli::SessionOptions,
+    watchdog: Option<Watchdog>,
+    config: &mut Config,
+    name: &str,
+    config_file: &Path,
+) -> Result<Client, Box<dyn std::error::Error>> {
+    let mut client = if let Some(path) = session.replay.as_ref() {
+        Client::replay(path, session.speed)?
+    } else {
+        let robot = unwrap!(
+            Some(config.robot(name).cloned().unwrap_or_default().with_env())
+                .filter(|x| *x != Default::default()),
+            "Unknown robot in the configuration. Please run `{exe} find-ip` first"
+        );
+        let (client, hostname) = Client::new_or_discover(
+            unwrap!(
+                robot.hostname.as_ref(),
+                "Missing hostname in the configuration. Please run `{exe} find-ip` first"
+            ),
+            unwrap!(
+                robot.blid.as_ref(),
+                "Missing BLID in the configuration. Please run `{exe} find-ip` first"
+            ),
+            unwrap!(
+                robot.password.as_ref(),
+                "Missing password in the configuration. Please run `{exe} get-password` first"
+            ),
+            0,
+            watchdog.map(|x| x.timeout).unwrap_or(roomba::KEEP_ALIVE),
+        )
+        .await?;
+
+        if let Some(hostname) = hostname {
+            eprintln!("The robot has a new IP address: {}", hostname);
+            // the address given by ROOMBA_HOSTNAME is not the one of the profile
+            let from_profile = config
+                .robot(name)
+                .map(|x| x.hostname == robot.hostname)
+                .unwrap_or(false);
+            if from_profile {
+                config.robot_mut(name).hostname = Some(hostname);
+                config.save(config_file);
+            } else {
+                eprintln!("Update ROOMBA_HOSTNAME to keep using it.");
+            }
+        }
+
+        client
+    };
+
+    if let Some(path) = session.record.as_ref() {
+        client.record(path)?;
+    }
+
+    if let Some(watchdog) = watchdog {
+        client.watchdog(watchdog);
+    }
+
+    Ok(client)
+}
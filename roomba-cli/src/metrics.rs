@@ -0,0 +1,264 @@
+use futures::select;
+use futures::stream::StreamExt;
+use roomba::{Client, ConnectionState, Event, MissionOutcome, State};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Method, Response};
+
+const PHASES: &[&str] = &[
+    "charge",
+    "run",
+    "stop",
+    "pause",
+    "stuck",
+    "evac",
+    "hmUsrDock",
+    "hmPostMsn",
+    "hmMidMsn",
+    "chargingerror",
+];
+
+/// What is exposed by the exporter.
+struct Metrics {
+    state: State,
+    connection: ConnectionState,
+    messages: u64,
+    completed: u64,
+    cancelled: u64,
+}
+
+/// Expose the state of the robot as Prometheus metrics.
+pub async fn run(client: &mut Client, listen: &str) -> Result<(), Box<dyn Error>> {
+    let server = tiny_http::Server::http(listen).map_err(|err| err.to_string())?;
+    let metrics = Arc::new(Mutex::new(Metrics {
+        state: State::default(),
+        connection: client.connection_state(),
+        messages: 0,
+        completed: 0,
+        cancelled: 0,
+    }));
+
+    {
+        let metrics = metrics.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let response = match (request.method(), request.url()) {
+                    (Method::Get, "/metrics") => {
+                        Response::from_string(render(&metrics.lock().unwrap())).with_header(
+                            "Content-Type: text/plain; version=0.0.4"
+                                .parse::<Header>()
+                                .unwrap(),
+                        )
+                    }
+                    _ => Response::from_string("not found").with_status_code(404),
+                };
+
+                if let Err(err) = request.respond(response) {
+                    debug!("could not send the response: {}", err);
+                }
+            }
+        });
+    }
+
+    loop {
+        select! {
+            message = client.events.next() => match message {
+                Some(Some(message)) => {
+                    let mut metrics = metrics.lock().unwrap();
+                    metrics.messages += 1;
+                    for event in metrics.state.update(&message).unwrap_or_default() {
+                        match event {
+                            Event::MissionEnded { outcome: MissionOutcome::Completed, .. } => {
+                                metrics.completed += 1
+                            }
+                            Event::MissionEnded { outcome: MissionOutcome::Cancelled, .. } => {
+                                metrics.cancelled += 1
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Some(None) => warn!("connection to the robot lost"),
+                None => break,
+            },
+            _ = client.states.next() => {},
+            complete => break,
+        }
+
+        metrics.lock().unwrap().connection = client.connection_state();
+    }
+
+    Err("the connection to the robot has been closed".into())
+}
+
+fn render(metrics: &Metrics) -> String {
+    let mut out = String::new();
+    let state = &metrics.state;
+    let status = state.status();
+    let get_f64 = |x| state.get(x).and_then(Value::as_f64);
+
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+        if samples.is_empty() {
+            return;
+        }
+
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{}{} {}", name, labels, value);
+        }
+    };
+    let sample = |value: Option<f64>| value.map(|x| vec![(String::new(), x)]).unwrap_or_default();
+    let flag = |value: bool| if value { 1.0 } else { 0.0 };
+
+    metric(
+        "roomba_up",
+        "gauge",
+        "Whether the state of the robot has been received.",
+        sample(Some(flag(state.is_synced()))),
+    );
+    metric(
+        "roomba_connection_state",
+        "gauge",
+        "State of the connection to the robot.",
+        [ConnectionState::Connected, ConnectionState::Disconnected]
+            .iter()
+            .map(|x| {
+                (
+                    format!("{{state=\"{}\"}}", x),
+                    flag(*x == metrics.connection),
+                )
+            })
+            .collect(),
+    );
+    metric(
+        "roomba_battery_percent",
+        "gauge",
+        "Battery level of the robot.",
+        sample(status.battery.map(|x| x as f64)),
+    );
+    metric(
+        "roomba_phase",
+        "gauge",
+        "Current phase of the mission.",
+        match status.phase.as_deref() {
+            Some(phase) => {
+                let mut phases = PHASES.to_vec();
+                if !phases.contains(&phase) {
+                    phases.push(phase);
+                }
+                phases
+                    .iter()
+                    .map(|x| (format!("{{phase=\"{}\"}}", x), flag(*x == phase)))
+                    .collect()
+            }
+            None => Vec::new(),
+        },
+    );
+    metric(
+        "roomba_docked",
+        "gauge",
+        "Whether the robot is on its dock.",
+        sample(status.phase.as_ref().map(|_| flag(status.docked))),
+    );
+    metric(
+        "roomba_bin_full",
+        "gauge",
+        "Whether the bin is full.",
+        sample(status.bin_full.map(flag)),
+    );
+    metric(
+        "roomba_bin_present",
+        "gauge",
+        "Whether the bin is in the robot.",
+        sample(status.bin_present.map(flag)),
+    );
+    metric(
+        "roomba_error_code",
+        "gauge",
+        "Error code of the robot (0 when there is no error).",
+        sample(status.error.map(|x| x as f64)),
+    );
+    metric(
+        "roomba_not_ready_code",
+        "gauge",
+        "Reason why the robot can not start a mission (0 when it is ready).",
+        sample(status.not_ready.map(|x| x as f64)),
+    );
+    metric(
+        "roomba_mission_minutes",
+        "gauge",
+        "Elapsed time of the current mission.",
+        sample(status.mission_minutes.map(|x| x as f64)),
+    );
+    metric(
+        "roomba_mission_area_sqft",
+        "gauge",
+        "Area cleaned during the current mission.",
+        sample(status.mission_area.map(|x| x as f64)),
+    );
+    metric(
+        "roomba_wifi_rssi_dbm",
+        "gauge",
+        "Signal strength of the Wi-Fi.",
+        sample(get_f64("/signal/rssi")),
+    );
+    metric(
+        "roomba_wifi_snr_db",
+        "gauge",
+        "Signal-to-noise ratio of the Wi-Fi.",
+        sample(get_f64("/signal/snr")),
+    );
+    metric(
+        "roomba_lifetime_hours",
+        "gauge",
+        "Running time of the robot since it has been made.",
+        sample(
+            get_f64("/bbrun/hr").map(|hours| hours + get_f64("/bbrun/min").unwrap_or(0.0) / 60.0),
+        ),
+    );
+    metric(
+        "roomba_lifetime_area_sqft",
+        "gauge",
+        "Area cleaned by the robot since it has been made (as reported in `bbrun`).",
+        sample(get_f64("/bbrun/sqft")),
+    );
+    metric(
+        "roomba_lifetime_missions_total",
+        "counter",
+        "Missions of the robot since it has been made.",
+        sample(get_f64("/bbmssn/nMssn")),
+    );
+    metric(
+        "roomba_lifetime_stuck_total",
+        "counter",
+        "Times the robot got stuck since it has been made.",
+        sample(get_f64("/bbrun/nStuck")),
+    );
+    metric(
+        "roomba_messages_total",
+        "counter",
+        "Messages received from the robot by the exporter.",
+        sample(Some(metrics.messages as f64)),
+    );
+    metric(
+        "roomba_missions_total",
+        "counter",
+        "Missions ended since the exporter started.",
+        vec![
+            (
+                "{outcome=\"completed\"}".to_string(),
+                metrics.completed as f64,
+            ),
+            (
+                "{outcome=\"cancelled\"}".to_string(),
+                metrics.cancelled as f64,
+            ),
+        ],
+    );
+
+    out
+}
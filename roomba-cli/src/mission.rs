@@ -0,0 +1,107 @@
+use crate::status::EXIT_ERROR;
+use futures::stream::StreamExt;
+use roomba::{Client, Event, MissionOutcome, State};
+use std::error::Error;
+use std::time::Duration;
+
+/// Exit code when the mission has been cancelled.
+pub const EXIT_CANCELLED: i32 = 4;
+/// Exit code when the mission did not end in time.
+pub const EXIT_TIMEOUT: i32 = 5;
+/// Exit code when the connection to the robot has been lost.
+pub const EXIT_DISCONNECTED: i32 = 6;
+
+/// Print the progress of the mission until it ends and return the exit code of the outcome. A lost
+/// connection ends the wait unless the client `reconnect`s.
+pub async fn wait(
+    client: &mut Client,
+    timeout: Option<u64>,
+    reconnect: bool,
+) -> Result<i32, Box<dyn Error>> {
+    let mission = wait_for_end(client, reconnect);
+
+    match timeout {
+        Some(timeout) => {
+            match async_std::future::timeout(Duration::from_secs(timeout), mission).await {
+                Ok(res) => res,
+                Err(_) => {
+                    println!("Timeout: the mission is still running");
+                    Ok(EXIT_TIMEOUT)
+                }
+            }
+        }
+        None => mission.await,
+    }
+}
+
+async fn wait_for_end(client: &mut Client, reconnect: bool) -> Result<i32, Box<dyn Error>> {
+    let mut state = State::default();
+    let mut progress = None;
+
+    while let Some(message) = client.events.next().await {
+        let message = match message {
+            Some(message) => message,
+            None if reconnect => continue,
+            None => {
+                println!("Connection to the robot lost");
+                return Ok(EXIT_DISCONNECTED);
+            }
+        };
+        let events = match state.update(&message) {
+            Some(events) => events,
+            None => continue,
+        };
+
+        let status = state.status();
+        let current = (
+            status.phase.clone(),
+            status.mission_minutes,
+            status.mission_area,
+        );
+        if status.in_mission() && progress.as_ref() != Some(&current) {
+            println!(
+                "{}: {} min, {} ft²",
+                status.phase.as_deref().unwrap_or("unknown"),
+                status.mission_minutes.unwrap_or(0),
+                status.mission_area.unwrap_or(0)
+            );
+            progress = Some(current);
+        }
+
+        for event in events {
+            match event {
+                Event::MissionEnded {
+                    outcome,
+                    minutes,
+                    area,
+                } => {
+                    println!(
+                        "Mission {}: {} min, {} ft²",
+                        match outcome {
+                            MissionOutcome::Completed => "completed",
+                            MissionOutcome::Cancelled => "cancelled",
+                        },
+                        minutes.unwrap_or(0),
+                        area.unwrap_or(0)
+                    );
+
+                    return Ok(match outcome {
+                        MissionOutcome::Completed => 0,
+                        MissionOutcome::Cancelled => EXIT_CANCELLED,
+                    });
+                }
+                Event::Stuck => {
+                    println!("The robot is stuck");
+                    return Ok(EXIT_ERROR);
+                }
+                Event::Error { code } => {
+                    println!("The robot is in error: {}", code);
+                    return Ok(EXIT_ERROR);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Err("the connection to the robot has been closed".into())
+}
@@ -0,0 +1,618 @@
+use futures::channel::mpsc;
+use futures::select;
+use futures::stream::StreamExt;
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use roomba::{Client, State};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const CONNECT: u8 = 0x10;
+const PUBLISH: u8 = 0x30;
+const PUBREL: u8 = 0x60;
+const SUBSCRIBE: u8 = 0x80;
+const UNSUBSCRIBE: u8 = 0xa0;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+struct Session {
+    filters: Vec<String>,
+    tx: std::sync::mpsc::Sender<Vec<u8>>,
+}
+
+/// Merged reported state of the robot, sent to every new subscriber since the robot only sends
+/// its full state once when the connection is established.
+#[derive(Default)]
+struct Retained {
+    topic: Option<String>,
+    state: State,
+}
+
+#[derive(Clone)]
+struct Broker {
+    sessions: Arc<Mutex<HashMap<usize, Session>>>,
+    retained: Arc<Mutex<Retained>>,
+    commands: mpsc::UnboundedSender<(String, Vec<u8>)>,
+    /// Username and password required from the clients.
+    credentials: Option<(String, String)>,
+}
+
+impl Broker {
+    fn publish(&self, topic: &str, payload: &[u8]) {
+        let packet = publish_packet(topic, payload);
+
+        self.sessions.lock().unwrap().retain(|_, session| {
+            if session.filters.iter().any(|x| topic_matches(x, topic)) {
+                session.tx.send(packet.clone()).is_ok()
+            } else {
+                true
+            }
+        });
+    }
+
+    fn retain(&self, message: &paho_mqtt::message::Message) {
+        let mut retained = self.retained.lock().unwrap();
+
+        if retained.state.update(message).is_some() {
+            retained.topic = Some(message.topic().to_string());
+        }
+    }
+}
+
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut filter = filter.split('/');
+    let mut topic = topic.split('/');
+
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => continue,
+            (Some(x), Some(y)) if x == y => continue,
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+    body.extend_from_slice(topic.as_bytes());
+    body.extend_from_slice(payload);
+
+    packet(PUBLISH, &body)
+}
+
+fn ack_packet(header: u8, packet_id: u16) -> Vec<u8> {
+    packet(header, &packet_id.to_be_bytes())
+}
+
+/// Return the header, the body and the total length of the first complete packet of the buffer.
+fn parse_packet(buffer: &[u8]) -> io::Result<Option<(u8, &[u8], usize)>> {
+    let mut length = 0;
+    let mut multiplier = 1;
+
+    for i in 1..5 {
+        let byte = match buffer.get(i) {
+            Some(&x) => x as usize,
+            None => return Ok(None),
+        };
+        length += (byte & 0x7f) * multiplier;
+        multiplier *= 128;
+
+        if byte & 0x80 == 0 {
+            let start = i + 1;
+            return Ok(buffer
+                .get(start..start + length)
+                .map(|body| (buffer[0], body, start + length)));
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed remaining length",
+    ))
+}
+
+fn read_u16(body: &[u8], pos: &mut usize) -> io::Result<u16> {
+    let bytes = body
+        .get(*pos..*pos + 2)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated packet"))?;
+    *pos += 2;
+
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_bytes<'a>(body: &'a [u8], pos: &mut usize) -> io::Result<&'a [u8]> {
+    let length = read_u16(body, pos)? as usize;
+    let bytes = body
+        .get(*pos..*pos + length)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated packet"))?;
+    *pos += length;
+
+    Ok(bytes)
+}
+
+fn read_string(body: &[u8], pos: &mut usize) -> io::Result<String> {
+    let bytes = read_bytes(body, pos)?;
+
+    String::from_utf8(bytes.to_vec()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Return the username and the password of the body of a CONNECT packet.
+fn parse_connect(body: &[u8]) -> io::Result<(Option<String>, Option<Vec<u8>>)> {
+    let mut pos = 0;
+    read_string(body, &mut pos)?; // protocol name
+    let flags = *body
+        .get(pos + 1)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated packet"))?;
+    pos += 2; // protocol level and flags
+    read_u16(body, &mut pos)?; // keep alive
+    read_string(body, &mut pos)?; // client identifier
+
+    if flags & 0x04 != 0 {
+        read_string(body, &mut pos)?; // will topic
+        read_bytes(body, &mut pos)?; // will message
+    }
+    let username = if flags & 0x80 != 0 {
+        Some(read_string(body, &mut pos)?)
+    } else {
+        None
+    };
+    let password = if flags & 0x40 != 0 {
+        Some(read_bytes(body, &mut pos)?.to_vec())
+    } else {
+        None
+    };
+
+    Ok((username, password))
+}
+
+/// Return the packet identifier and the topic filters of the body of a SUBSCRIBE packet.
+fn parse_subscribe(body: &[u8]) -> io::Result<(u16, Vec<String>)> {
+    let mut pos = 0;
+    let packet_id = read_u16(body, &mut pos)?;
+    let mut filters = Vec::new();
+
+    while pos < body.len() {
+        filters.push(read_string(body, &mut pos)?);
+        // requested QoS
+        if body.get(pos).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated packet",
+            ));
+        }
+        pos += 1;
+    }
+
+    Ok((packet_id, filters))
+}
+
+fn session(mut stream: Box<dyn Stream>, id: usize, broker: Broker) -> io::Result<()> {
+    let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    let mut connected = false;
+
+    loop {
+        while let Ok(data) = rx.try_recv() {
+            stream.write_all(&data)?;
+        }
+
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(length) => buffer.extend_from_slice(&chunk[..length]),
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        }
+
+        while let Some((header, body, length)) = parse_packet(&buffer)? {
+            let mut pos = 0;
+
+            match header & 0xf0 {
+                CONNECT => {
+                    if let Some((username, password)) = broker.credentials.as_ref() {
+                        let given = parse_connect(body)?;
+                        if given != (Some(username.clone()), Some(password.as_bytes().to_vec())) {
+                            warn!("session {}: bad user name or password", id);
+                            // CONNACK: refused, bad user name or password
+                            stream.write_all(&[0x20, 0x02, 0x00, 0x04])?;
+                            return Ok(());
+                        }
+                    }
+
+                    connected = true;
+                    broker.sessions.lock().unwrap().insert(
+                        id,
+                        Session {
+                            filters: Vec::new(),
+                            tx: tx.clone(),
+                        },
+                    );
+                    stream.write_all(&[0x20, 0x02, 0x00, 0x00])?;
+                }
+                _ if !connected => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "first packet is not CONNECT",
+                    ))
+                }
+                PUBLISH => {
+                    let qos = (header >> 1) & 0x03;
+                    let topic = read_string(body, &mut pos)?;
+                    if qos > 0 {
+                        let packet_id = read_u16(body, &mut pos)?;
+                        stream.write_all(&ack_packet(
+                            if qos == 1 { 0x40 } else { 0x50 },
+                            packet_id,
+                        ))?;
+                    }
+                    debug!("forwarding message from session {} to {}", id, topic);
+                    let _ = broker
+                        .commands
+                        .unbounded_send((topic, body[pos..].to_vec()));
+                }
+                PUBREL => {
+                    let packet_id = read_u16(body, &mut pos)?;
+                    stream.write_all(&ack_packet(0x70, packet_id))?;
+                }
+                SUBSCRIBE => {
+                    let (packet_id, filters) = parse_subscribe(body)?;
+
+                    let mut suback = packet_id.to_be_bytes().to_vec();
+                    suback.extend(filters.iter().map(|_| 0x00));
+                    stream.write_all(&packet(0x90, &suback))?;
+
+                    let retained = {
+                        let retained = broker.retained.lock().unwrap();
+                        retained
+                            .topic
+                            .clone()
+                            .filter(|topic| filters.iter().any(|x| topic_matches(x, topic)))
+                            .map(|topic| {
+                                let payload = serde_json::json!({
+                                    "state": { "reported": retained.state.reported() },
+                                });
+                                publish_packet(&topic, payload.to_string().as_bytes())
+                            })
+                    };
+
+                    if let Some(session) = broker.sessions.lock().unwrap().get_mut(&id) {
+                        session.filters.extend(filters);
+                    }
+                    if let Some(retained) = retained {
+                        stream.write_all(&retained)?;
+                    }
+                }
+                UNSUBSCRIBE => {
+                    let packet_id = read_u16(body, &mut pos)?;
+                    let mut filters = Vec::new();
+                    while pos < body.len() {
+                        filters.push(read_string(body, &mut pos)?);
+                    }
+
+                    if let Some(session) = broker.sessions.lock().unwrap().get_mut(&id) {
+                        session.filters.retain(|x| !filters.contains(x));
+                    }
+                    stream.write_all(&ack_packet(0xb0, packet_id))?;
+                }
+                PINGREQ => stream.write_all(&[0xd0, 0x00])?,
+                DISCONNECT => return Ok(()),
+                _ => {}
+            }
+
+            buffer.drain(..length);
+        }
+    }
+}
+
+fn listen(listener: TcpListener, acceptor: Option<SslAcceptor>, broker: Broker) {
+    for (id, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("could not accept connection: {}", err);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let broker = broker.clone();
+
+        thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            debug!("session {} opened from {:?}", id, peer);
+
+            let stream: io::Result<Box<dyn Stream>> = match acceptor {
+                Some(acceptor) => acceptor
+                    .accept(stream)
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))
+                    .and_then(|stream| {
+                        set_timeout(stream.get_ref())?;
+                        Ok(Box::new(stream) as Box<dyn Stream>)
+                    }),
+                None => set_timeout(&stream).map(|_| Box::new(stream) as Box<dyn Stream>),
+            };
+
+            if let Err(err) = stream.and_then(|stream| session(stream, id, broker.clone())) {
+                debug!("session {} closed with error: {}", id, err);
+            }
+            broker.sessions.lock().unwrap().remove(&id);
+            debug!("session {} closed", id);
+        });
+    }
+}
+
+fn set_timeout(stream: &TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_millis(100)))
+}
+
+fn acceptor(cert: &Path, key: &Path) -> Result<SslAcceptor, Box<dyn Error>> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_private_key_file(key, SslFiletype::PEM)?;
+    builder.set_certificate_chain_file(cert)?;
+    builder.check_private_key()?;
+
+    Ok(builder.build())
+}
+
+pub async fn run(
+    client: &mut Client,
+    listen_address: &str,
+    tls: Option<(&Path, &Path)>,
+    credentials: Option<(&str, &str)>,
+) -> Result<(), Box<dyn Error>> {
+    let acceptor = match tls {
+        Some((cert, key)) => Some(acceptor(cert, key)?),
+        None => None,
+    };
+    let listener = TcpListener::bind(listen_address)?;
+    let (tx, mut commands) = mpsc::unbounded();
+    let broker = Broker {
+        sessions: Default::default(),
+        retained: Default::default(),
+        commands: tx,
+        credentials: credentials.map(|(x, y)| (x.to_string(), y.to_string())),
+    };
+
+    println!(
+        "Listening on {}://{}",
+        if acceptor.is_some() { "mqtts" } else { "mqtt" },
+        listener.local_addr()?
+    );
+    if broker.credentials.is_none() && !listener.local_addr()?.ip().is_loopback() {
+        println!(
+            "Warning: any client of the network can control the robot, use --username and \
+            --password to require credentials"
+        );
+    }
+    thread::spawn({
+        let broker = broker.clone();
+        move || listen(listener, acceptor, broker)
+    });
+
+    loop {
+        select! {
+            ev = client.events.next() => {
+                if let Some(ev) = ev.flatten() {
+                    broker.retain(&ev);
+                    broker.publish(ev.topic(), ev.payload());
+                } else {
+                    return Err("connection to the robot lost".into());
+                }
+            },
+            state = client.states.next() => {
+                if let Some(state) = state {
+                    warn!("connection to the robot: {}", state);
+                }
+            },
+            command = commands.next() => {
+                if let Some((topic, payload)) = command {
+                    if let Err(err) = client.publish(topic, payload).await {
+                        warn!("could not forward the message to the robot: {}", err);
+                    }
+                }
+            },
+            complete => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscribe_body(packet_id: u16, filters: &[&str]) -> Vec<u8> {
+        let mut body = packet_id.to_be_bytes().to_vec();
+        for filter in filters {
+            body.extend_from_slice(&(filter.len() as u16).to_be_bytes());
+            body.extend_from_slice(filter.as_bytes());
+            body.push(0x00);
+        }
+        body
+    }
+
+    #[test]
+    fn topic_filters() {
+        let topic = "$aws/things/blid/shadow/update";
+
+        assert!(topic_matches(topic, topic));
+        assert!(topic_matches("#", topic));
+        assert!(topic_matches("$aws/things/+/shadow/update", topic));
+        assert!(topic_matches("$aws/things/blid/#", topic));
+        assert!(topic_matches("$aws/things/blid/shadow/update/#", topic));
+        assert!(topic_matches("+/+/+/+/+", topic));
+        assert!(!topic_matches("$aws/things/+", topic));
+        assert!(!topic_matches("$aws/things/+/shadow", topic));
+        assert!(!topic_matches("$aws/things/other/#", topic));
+        assert!(!topic_matches("$aws/things/blid/shadow/update/+", topic));
+    }
+
+    #[test]
+    fn short_packet() {
+        let packet = packet(PINGREQ, &[]);
+
+        assert_eq!(packet, vec![0xc0, 0x00]);
+        assert_eq!(parse_packet(&packet).unwrap(), Some((PINGREQ, &[][..], 2)));
+    }
+
+    #[test]
+    fn remaining_length_over_127_bytes() {
+        for &length in &[127, 128, 321, 16_383, 16_384, 2_097_152] {
+            let body = vec![0x42; length];
+            let packet = packet(PUBLISH, &body);
+            let header_length = match length {
+                0..=127 => 2,
+                128..=16_383 => 3,
+                16_384..=2_097_151 => 4,
+                _ => 5,
+            };
+
+            assert_eq!(packet.len(), header_length + length, "length {}", length);
+            assert_eq!(
+                parse_packet(&packet).unwrap(),
+                Some((PUBLISH, &body[..], packet.len())),
+                "length {}",
+                length
+            );
+        }
+
+        assert_eq!(packet(PUBLISH, &[0; 321])[1..3], [0xc1, 0x02]);
+    }
+
+    #[test]
+    fn truncated_packets() {
+        let packet = publish_packet("topic", &[0; 200]);
+
+        for length in 0..packet.len() {
+            assert_eq!(parse_packet(&packet[..length]).unwrap(), None);
+        }
+        assert!(parse_packet(&[PUBLISH, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+    }
+
+    #[test]
+    fn first_packet_of_the_buffer() {
+        let mut buffer = publish_packet("a/b", b"payload");
+        let length = buffer.len();
+        buffer.extend(ack_packet(0x40, 1));
+
+        let (header, body, parsed) = parse_packet(&buffer).unwrap().unwrap();
+        assert_eq!(header, PUBLISH);
+        assert_eq!(parsed, length);
+
+        let mut pos = 0;
+        assert_eq!(read_string(body, &mut pos).unwrap(), "a/b");
+        assert_eq!(&body[pos..], b"payload");
+
+        assert_eq!(
+            parse_packet(&buffer[parsed..]).unwrap(),
+            Some((0x40, &[0x00, 0x01][..], 4))
+        );
+    }
+
+    #[test]
+    fn subscribe() {
+        let body = subscribe_body(10, &["$aws/things/+/shadow/#", "wifistat"]);
+
+        assert_eq!(
+            parse_subscribe(&body).unwrap(),
+            (
+                10,
+                vec!["$aws/things/+/shadow/#".to_string(), "wifistat".to_string()]
+            )
+        );
+        assert_eq!(parse_subscribe(&body[..2]).unwrap(), (10, vec![]));
+    }
+
+    fn connect_body(flags: u8, fields: &[&[u8]]) -> Vec<u8> {
+        let mut body = vec![0x00, 0x04];
+        body.extend_from_slice(b"MQTT");
+        body.extend_from_slice(&[0x04, flags, 0x00, 0x3c]);
+        for field in fields {
+            body.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            body.extend_from_slice(field);
+        }
+        body
+    }
+
+    #[test]
+    fn connect() {
+        assert_eq!(
+            parse_connect(&connect_body(0x02, &[b"client"])).unwrap(),
+            (None, None)
+        );
+        assert_eq!(
+            parse_connect(&connect_body(0xc2, &[b"client", b"user", b"secret"])).unwrap(),
+            (Some("user".to_string()), Some(b"secret".to_vec()))
+        );
+        // with a will message
+        assert_eq!(
+            parse_connect(&connect_body(
+                0xc6,
+                &[b"client", b"will/topic", b"offline", b"user", b"secret"]
+            ))
+            .unwrap(),
+            (Some("user".to_string()), Some(b"secret".to_vec()))
+        );
+    }
+
+    #[test]
+    fn truncated_connect() {
+        let body = connect_body(0xc2, &[b"client", b"user", b"secret"]);
+
+        for length in 0..body.len() {
+            assert!(parse_connect(&body[..length]).is_err(), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn truncated_subscribe() {
+        let body = subscribe_body(10, &["a/b"]);
+
+        for length in 0..body.len() {
+            if length == 2 {
+                continue;
+            }
+            assert!(
+                parse_subscribe(&body[..length]).is_err(),
+                "length {}",
+                length
+            );
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use crate::status::wait_for_state;
+use futures::stream::StreamExt;
+use roomba::{Client, State};
+use std::error::Error;
+use std::time::Duration;
+
+/// Time without message after which the shadow is considered complete.
+const QUIET: Duration = Duration::from_secs(1);
+
+/// Publish an arbitrary JSON payload to the robot.
+pub async fn publish(client: &Client, topic: &str, payload: &str) -> Result<(), Box<dyn Error>> {
+    serde_json::from_str::<serde_json::Value>(payload)
+        .map_err(|err| format!("invalid JSON payload: {}", err))?;
+
+    client.publish(topic, payload).await?;
+
+    Ok(())
+}
+
+/// Wait for the state of the robot and the rest of its shadow.
+pub async fn wait_for_shadow(
+    client: &mut Client,
+    timeout: Duration,
+) -> Result<State, Box<dyn Error>> {
+    let mut state = wait_for_state(client, timeout).await?;
+
+    // the robot sends its shadow in many messages, the rest usually follows immediately
+    while let Ok(Some(Some(message))) =
+        async_std::future::timeout(QUIET, client.events.next()).await
+    {
+        state.update(&message);
+    }
+
+    Ok(state)
+}
+
+/// Print the reported shadow of the robot once synced.
+pub async fn dump(client: &mut Client, timeout: u64) -> Result<(), Box<dyn Error>> {
+    let state = wait_for_shadow(client, Duration::from_secs(timeout)).await?;
+
+    println!("{}", serde_json::to_string_pretty(state.reported())?);
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use crate::config::{Config, Room};
+use futures::stream::StreamExt;
+use roomba::{api, Client};
+use serde::Deserialize;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Deserialize, Debug)]
+struct LastCommand {
+    pmap_id: Option<String>,
+    user_pmapv_id: Option<String>,
+    #[serde(default)]
+    regions: Vec<api::Region>,
+}
+
+/// Region type of the zones defined in the app.
+const ZONE: &str = "zid";
+
+fn prompt(region: &api::Region) -> std::io::Result<String> {
+    let mut line = String::new();
+
+    if region.type_ == ZONE {
+        print!(
+            "Name of the zone {} (leave empty to skip): ",
+            region.region_id
+        );
+    } else {
+        print!(
+            "Name of the region {} (type: {}, leave empty to skip): ",
+            region.region_id, region.type_
+        );
+    }
+    std::io::stdout().flush()?;
+    std::io::stdin().read_line(&mut line)?;
+
+    Ok(line.trim().to_string())
+}
+
+/// Name the regions of the missions started with the app and save them to the configuration.
+/// Only the regions of the map of the profile are learned: the rooms and zones of a profile all
+/// belong to its `pmap_id`.
+pub async fn learn(
+    client: &mut Client,
+    config: &mut Config,
+    name: &str,
+    config_file: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut skipped: Vec<api::Region> = Vec::new();
+
+    println!(
+        "Start a cleaning job on the rooms or zones you want to learn with the iRobot app. Press Ctrl+C \
+        when done."
+    );
+
+    while let Some(message) = client.events.next().await {
+        let message = match message {
+            Some(message) => message,
+            None => return Err("connection to the robot lost".into()),
+        };
+        let last_command = match serde_json::from_slice::<serde_json::Value>(message.payload())
+            .ok()
+            .and_then(|x| x.pointer("/state/reported/lastCommand").cloned())
+            .and_then(|x| serde_json::from_value::<LastCommand>(x).ok())
+        {
+            Some(last_command) if !last_command.regions.is_empty() => last_command,
+            _ => continue,
+        };
+
+        let robot = config.robot_mut(name);
+        match (last_command.pmap_id, last_command.user_pmapv_id) {
+            (Some(pmap_id), Some(user_pmapv_id)) if robot.pmap_id.is_none() => {
+                println!("Map found: {} (version: {})", pmap_id, user_pmapv_id);
+                robot.pmap_id = Some(pmap_id);
+                robot.user_pmapv_id = Some(user_pmapv_id);
+                config.save(config_file);
+            }
+            (Some(pmap_id), _) if robot.pmap_id.as_ref() != Some(&pmap_id) => {
+                println!(
+                    "Ignoring regions of map {} which is not the map of the configuration, run \
+                    `setup` to use this map",
+                    pmap_id
+                );
+                continue;
+            }
+            _ => {}
+        }
+
+        for region in last_command.regions {
+            let known = config
+                .robot_mut(name)
+                .regions()
+                .map(|x| &x.region)
+                .chain(skipped.iter())
+                .any(|x| x.region_id == region.region_id && x.type_ == region.type_);
+            if known {
+                continue;
+            }
+
+            let room_name = prompt(&region)?;
+            if room_name.is_empty() {
+                skipped.push(region);
+            } else {
+                let robot = config.robot_mut(name);
+                let regions = if region.type_ == ZONE {
+                    println!("Saved zone: {}", room_name);
+                    &mut robot.zones
+                } else {
+                    println!("Saved: {}", room_name);
+                    &mut robot.rooms
+                };
+                regions.push(Room {
+                    name: room_name,
+                    region,
+                });
+                config.save(config_file);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,302 @@
+use crate::cli;
+use crate::config::Robot;
+use futures::channel::mpsc;
+use futures::select;
+use futures::stream::StreamExt;
+use roomba::{api, Client, ConnectionState, State};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::error::Error;
+use std::io::{Cursor, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Method, Request, Response};
+
+type Reply = std::sync::mpsc::Sender<Result<(), String>>;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// State shared between the connection to the robot and the HTTP threads.
+#[derive(Default)]
+struct Shared {
+    state: State,
+    /// The connection to the robot is lost: the state is not up to date.
+    lost: bool,
+    /// Server-Sent Events streams.
+    subscribers: Vec<std::sync::mpsc::Sender<String>>,
+}
+
+#[derive(Deserialize, Default)]
+struct Clean {
+    #[serde(default)]
+    rooms: Vec<String>,
+    #[serde(default)]
+    ordered: bool,
+}
+
+/// Serve the state of the robot, accept commands over HTTP and serve the web dashboard.
+pub async fn run(client: &mut Client, robot: &Robot, listen: &str) -> Result<(), Box<dyn Error>> {
+    let server = tiny_http::Server::http(listen).map_err(|err| err.to_string())?;
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    let (tx, mut commands) = mpsc::unbounded::<(api::Message, Reply)>();
+
+    {
+        let shared = shared.clone();
+        let robot = Arc::new(robot.clone());
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let shared = shared.clone();
+                let robot = robot.clone();
+                let tx = tx.clone();
+                thread::spawn(move || handle(request, &shared, &tx, &robot));
+            }
+        });
+    }
+
+    let mut last_status = None;
+
+    loop {
+        select! {
+            message = client.events.next() => match message {
+                Some(Some(message)) => {
+                    let mut shared = shared.lock().unwrap();
+                    let events = match shared.state.update(&message) {
+                        Some(events) if shared.state.is_synced() => events,
+                        _ => continue,
+                    };
+
+                    let mut data: Vec<String> = events.iter().map(|x| sse("event", x)).collect();
+                    let status = shared.state.status();
+                    if last_status.as_ref() != Some(&status) {
+                        data.push(sse("status", &status));
+                        last_status = Some(status);
+                    }
+
+                    shared
+                        .subscribers
+                        .retain(|x| data.iter().all(|data| x.send(data.clone()).is_ok()));
+                }
+                Some(None) => return Err("the connection to the robot has been lost".into()),
+                None => break,
+            },
+            state = client.states.next() => if let Some(state) = state {
+                warn!("connection to the robot: {}", state);
+                shared.lock().unwrap().lost = state != ConnectionState::Connected;
+            },
+            command = commands.next() => {
+                if let Some((message, reply)) = command {
+                    let res = client.send_message(&message).await.map_err(|err| err.to_string());
+                    let _ = reply.send(res);
+                }
+            },
+            complete => break,
+        }
+    }
+
+    Err("the connection to the robot has been closed".into())
+}
+
+fn handle(
+    mut request: Request,
+    shared: &Mutex<Shared>,
+    commands: &mpsc::UnboundedSender<(api::Message, Reply)>,
+    robot: &Robot,
+) {
+    debug!("{} {}", request.method(), request.url());
+
+    if *request.method() == Method::Post {
+        if let Err(response) = check_post(&request) {
+            if let Err(err) = request.respond(response) {
+                debug!("could not send the response: {}", err);
+            }
+            return;
+        }
+    }
+
+    let url = request.url().to_string();
+    let response = match (request.method(), url.as_str()) {
+        (Method::Get, "/") => Response::from_string(DASHBOARD).with_header(
+            "Content-Type: text/html; charset=utf-8"
+                .parse::<Header>()
+                .unwrap(),
+        ),
+        (Method::Get, "/status") => {
+            let shared = shared.lock().unwrap();
+            if shared.lost {
+                error(503, "the connection to the robot is lost")
+            } else if shared.state.is_synced() {
+                json(200, &shared.state.status())
+            } else {
+                error(503, "the state of the robot is not known yet")
+            }
+        }
+        (Method::Get, "/rooms") => json(200, &robot.rooms),
+        (Method::Get, "/zones") => json(200, &robot.zones),
+        (Method::Get, "/events") => return events(request, shared),
+        (Method::Post, "/clean") => {
+            let mut body = String::new();
+            let clean = request
+                .as_reader()
+                .read_to_string(&mut body)
+                .map_err(|err| err.to_string())
+                .and_then(|_| {
+                    if body.trim().is_empty() {
+                        Ok(Clean::default())
+                    } else {
+                        serde_json::from_str::<Clean>(&body).map_err(|err| err.to_string())
+                    }
+                });
+
+            match clean {
+                Ok(Clean { rooms, .. }) if rooms.is_empty() => {
+                    send(commands, cli::Command::Start, robot)
+                }
+                Ok(Clean { rooms, ordered }) => send(
+                    commands,
+                    cli::Command::StartRegions {
+                        ordered,
+                        regions: rooms,
+                    },
+                    robot,
+                ),
+                Err(err) => error(400, &err),
+            }
+        }
+        (Method::Post, path) if path.starts_with("/commands/") => {
+            let command = match &path["/commands/".len()..] {
+                "start" => Some(cli::Command::Start),
+                "clean" => Some(cli::Command::Clean),
+                "pause" => Some(cli::Command::Pause),
+                "resume" => Some(cli::Command::Resume),
+                "stop" => Some(cli::Command::Stop),
+                "dock" => Some(cli::Command::Dock),
+                "evac" => Some(cli::Command::Evac),
+                "train" => Some(cli::Command::Train),
+                _ => None,
+            };
+
+            match command {
+                Some(command) => send(commands, command, robot),
+                None => error(404, "unknown command"),
+            }
+        }
+        _ => error(404, "not found"),
+    };
+
+    if let Err(err) = request.respond(response) {
+        debug!("could not send the response: {}", err);
+    }
+}
+
+/// Refuse the commands a web page of another site could send: a JSON body can't be posted
+/// across origins without a CORS preflight, which is never allowed.
+fn check_post(request: &Request) -> Result<(), Response<Cursor<Vec<u8>>>> {
+    let content_type = header(request, "Content-Type")
+        .and_then(|x| x.split(';').next())
+        .map(str::trim);
+    if !matches!(content_type, Some(x) if x.eq_ignore_ascii_case("application/json")) {
+        return Err(error(415, "the content type must be application/json"));
+    }
+
+    if let Some(origin) = header(request, "Origin") {
+        let origin = origin
+            .trim_start_matches("http://")
+            .trim_start_matches("https://");
+        if Some(origin) != header(request, "Host") {
+            return Err(error(403, "cross-origin requests are refused"));
+        }
+    }
+
+    Ok(())
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|x| x.field.equiv(name))
+        .map(|x| x.value.as_str())
+}
+
+/// Send a command to the robot and wait until it has been published.
+fn send(
+    commands: &mpsc::UnboundedSender<(api::Message, Reply)>,
+    command: cli::Command,
+    robot: &Robot,
+) -> Response<Cursor<Vec<u8>>> {
+    let message = match command.into_command_with_extra(robot) {
+        Ok((command, extra)) => api::Message::new_command(command, extra),
+        Err(err) => return error(400, &err),
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    if commands.unbounded_send((message, tx)).is_err() {
+        return error(503, "the connection to the robot has been closed");
+    }
+
+    match rx.recv() {
+        Ok(Ok(())) => json(202, &json!({ "sent": true })),
+        Ok(Err(err)) => error(502, &err),
+        Err(_) => error(503, "the connection to the robot has been closed"),
+    }
+}
+
+/// Stream the changes of the status and the events of the robot as Server-Sent Events.
+fn events(request: Request, shared: &Mutex<Shared>) {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let status = {
+        let mut shared = shared.lock().unwrap();
+        shared.subscribers.push(tx);
+        if shared.state.is_synced() {
+            Some(shared.state.status())
+        } else {
+            None
+        }
+    };
+
+    // the response is written by hand because tiny_http buffers chunked responses
+    let mut writer = request.into_writer();
+    let res = writer
+        .write_all(
+            b"HTTP/1.1 200 OK\r\n\
+            Content-Type: text/event-stream\r\n\
+            Cache-Control: no-cache\r\n\
+            Connection: close\r\n\r\n",
+        )
+        .and_then(|_| match status {
+            Some(status) => writer.write_all(sse("status", &status).as_bytes()),
+            None => Ok(()),
+        })
+        .and_then(|_| writer.flush());
+    if res.is_err() {
+        return;
+    }
+
+    for data in rx {
+        if writer
+            .write_all(data.as_bytes())
+            .and_then(|_| writer.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn sse<T: Serialize>(event: &str, value: &T) -> String {
+    format!(
+        "event: {}\ndata: {}\n\n",
+        event,
+        serde_json::to_string(value).expect("serialization failed")
+    )
+}
+
+fn json<T: Serialize>(code: u16, value: &T) -> Response<Cursor<Vec<u8>>> {
+    Response::from_string(serde_json::to_string(value).expect("serialization failed"))
+        .with_status_code(code)
+        .with_header("Content-Type: application/json".parse::<Header>().unwrap())
+}
+
+fn error(code: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+    json(code, &json!({ "error": message }))
+}
@@ -0,0 +1,231 @@
+use crate::config::{Config, Robot};
+use crate::find_ip;
+use crate::raw::wait_for_shadow;
+use crate::rooms;
+use roomba::{Client, Discovery};
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Number of attempts to get the password before giving up.
+const PAIRING_ATTEMPTS: u32 = 20;
+/// Delay between two attempts to get the password.
+const PAIRING_DELAY: u64 = 3;
+/// Time given to the robot to send its state once connected.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Find a robot on the network, pair with it, check the connection and detect its map before
+/// saving its profile, then optionally learn the names of its rooms.
+pub async fn run(
+    config: &mut Config,
+    config_file: &Path,
+    profile: Option<String>,
+    timeout: u64,
+) -> Result<(), Box<dyn Error>> {
+    println!("Step 1/5: looking for robots for {} seconds...", timeout);
+    let robots = Discovery::find_all(Duration::from_secs(timeout))?;
+    if robots.is_empty() {
+        return Err(
+            "no robot found on the network, check that the robot and this computer \
+            are on the same network"
+                .into(),
+        );
+    }
+
+    println!();
+    find_ip::print_table(&robots);
+    println!();
+
+    println!("Step 2/5: selecting the robot");
+    let info = if robots.len() == 1 {
+        &robots[0]
+    } else {
+        match find_ip::choose("Robot to set up", robots.len())? {
+            Some(i) => &robots[i],
+            None => return Err("setup cancelled".into()),
+        }
+    };
+    let blid = info
+        .robot_id()
+        .map_err(|err| format!("cannot set up the robot at {}: {}", info.ip, err))?;
+    let name = find_ip::profile_name(config, info, &blid, profile);
+    println!("Setting up {} as robot: {}", info.ip, name);
+    println!();
+
+    let mut robot = config.robot(&name).cloned().unwrap_or_default();
+    robot.hostname = Some(info.ip.clone());
+    robot.blid = Some(blid.clone());
+
+    println!("Step 3/5: pairing");
+    let existing = match robot.password.clone() {
+        Some(password) => {
+            println!("Trying the password of the configuration...");
+            match Client::new(&info.ip, blid.as_str(), password, 0).await {
+                Ok(client) => Some(client),
+                Err(err) => {
+                    println!("The password of the configuration does not work: {}", err);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let mut client = match existing {
+        Some(client) => client,
+        None => {
+            let password = pair(&info.ip)?;
+            println!("Password received.");
+            robot.password = Some(password.clone());
+
+            Client::new(&info.ip, blid.as_str(), password, 0)
+                .await
+                .map_err(|err| format!("could not connect to the robot: {}", err))?
+        }
+    };
+    println!();
+
+    println!("Step 4/5: checking the connection");
+    let state = wait_for_shadow(&mut client, SYNC_TIMEOUT).await?;
+    let status = state.status();
+    println!(
+        "Connected to {} (battery: {}%, phase: {})",
+        status.name.as_deref().unwrap_or(&name),
+        status.battery.unwrap_or(0),
+        status.phase.as_deref().unwrap_or("unknown"),
+    );
+    println!();
+
+    println!("Step 5/5: detecting the map");
+    let maps = state
+        .get("/pmaps")
+        .and_then(|x| x.as_array())
+        .map(|x| {
+            x.iter()
+                .filter_map(|x| x.as_object())
+                .filter_map(|x| x.iter().next())
+                .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let map = match maps.len() {
+        0 => None,
+        1 => maps.into_iter().next(),
+        _ => {
+            for (i, (pmap_id, user_pmapv_id)) in maps.iter().enumerate() {
+                println!("{}  {} (version: {})", i + 1, pmap_id, user_pmapv_id);
+            }
+            find_ip::choose("Map to use", maps.len())?.map(|i| maps[i].clone())
+        }
+    };
+    match map {
+        Some((pmap_id, user_pmapv_id)) => {
+            println!("Map found: {} (version: {})", pmap_id, user_pmapv_id);
+            if robot.pmap_id.is_some() && robot.pmap_id.as_ref() != Some(&pmap_id) {
+                println!("The map changed, the rooms and zones of the previous map are forgotten.");
+                robot.rooms.clear();
+                robot.zones.clear();
+            }
+            robot.pmap_id = Some(pmap_id);
+            robot.user_pmapv_id = Some(user_pmapv_id);
+        }
+        None if robot.pmap_id.is_some() => {
+            println!("No map reported by the robot, keeping the map of the configuration.");
+        }
+        None => {
+            println!(
+                "No map found: cleaning rooms will not be available until the robot has mapped \
+                your home."
+            );
+        }
+    }
+    println!();
+
+    validate(&robot)?;
+    let has_map = robot.pmap_id.is_some();
+    *config.robot_mut(&name) = robot;
+    if !config.save(config_file) {
+        return Err("the profile could not be saved".into());
+    }
+    println!("Saved as robot: {}", name);
+
+    if has_map && confirm("Do you want to name the rooms now?")? {
+        println!();
+        rooms::learn(&mut client, config, &name, config_file).await?;
+    }
+
+    Ok(())
+}
+
+/// Ask for the password of the robot until the user presses the Home button.
+fn pair(hostname: &str) -> Result<String, Box<dyn Error>> {
+    println!(
+        "Hold the Home button of the robot for 2 seconds until the ring led is blinking blue, \
+        then press Enter."
+    );
+    std::io::stdin().read_line(&mut String::new())?;
+
+    for attempt in 1..=PAIRING_ATTEMPTS {
+        match Client::get_password(hostname) {
+            Ok(password) => return Ok(password),
+            Err(err) if attempt < PAIRING_ATTEMPTS => {
+                println!("Attempt {}/{} failed: {}", attempt, PAIRING_ATTEMPTS, err);
+                countdown(PAIRING_DELAY)?;
+            }
+            Err(err) => {
+                return Err(format!(
+                    "could not get the password after {} attempts: {}",
+                    PAIRING_ATTEMPTS, err
+                )
+                .into())
+            }
+        }
+    }
+
+    unreachable!()
+}
+
+fn countdown(seconds: u64) -> std::io::Result<()> {
+    let stdout = std::io::stdout();
+
+    for remaining in (1..=seconds).rev() {
+        let mut fh = stdout.lock();
+        write!(fh, "\rRetrying in {}s...", remaining)?;
+        fh.flush()?;
+        drop(fh);
+        thread::sleep(Duration::from_secs(1));
+    }
+    println!();
+
+    Ok(())
+}
+
+fn confirm(question: &str) -> std::io::Result<bool> {
+    let mut line = String::new();
+
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    std::io::stdin().read_line(&mut line)?;
+
+    Ok(matches!(line.trim(), "y" | "Y" | "yes"))
+}
+
+/// Check that the profile has everything needed to control the robot.
+fn validate(robot: &Robot) -> Result<(), Box<dyn Error>> {
+    for (field, value) in &[
+        ("hostname", &robot.hostname),
+        ("BLID", &robot.blid),
+        ("password", &robot.password),
+    ] {
+        if value.is_none() {
+            return Err(format!("the profile is incomplete: missing {}", field).into());
+        }
+    }
+
+    if robot.pmap_id.is_some() != robot.user_pmapv_id.is_some() {
+        return Err("the profile is incomplete: pmap_id and user_pmapv_id go together".into());
+    }
+
+    Ok(())
+}
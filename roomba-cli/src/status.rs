@@ -0,0 +1,104 @@
+use futures::stream::StreamExt;
+use roomba::{Client, State, Status};
+use std::error::Error;
+use std::time::Duration;
+
+/// Exit code when the robot is stuck or in error.
+pub const EXIT_ERROR: i32 = 2;
+/// Exit code when the robot needs attention (bin full or missing, not ready).
+pub const EXIT_ATTENTION: i32 = 3;
+
+/// Receive messages until the state of the robot is known.
+pub async fn wait_for_state(
+    client: &mut Client,
+    timeout: Duration,
+) -> Result<State, Box<dyn Error>> {
+    let mut state = State::default();
+
+    let synced = async_std::future::timeout(timeout, async {
+        while let Some(message) = client.events.next().await {
+            if let Some(message) = message {
+                state.update(&message);
+                if state.is_synced() {
+                    return true;
+                }
+            }
+        }
+
+        false
+    })
+    .await
+    .unwrap_or(false);
+
+    if synced {
+        Ok(state)
+    } else {
+        Err("the robot did not send its state in time".into())
+    }
+}
+
+pub async fn run(client: &mut Client, json: bool, timeout: u64) -> Result<i32, Box<dyn Error>> {
+    let status = wait_for_state(client, Duration::from_secs(timeout))
+        .await?
+        .status();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        print(&status);
+    }
+
+    Ok(if status.is_stuck() {
+        EXIT_ERROR
+    } else if status.needs_attention() {
+        EXIT_ATTENTION
+    } else {
+        0
+    })
+}
+
+fn print(status: &Status) {
+    let unknown = || "unknown".to_string();
+
+    if let Some(name) = status.name.as_ref() {
+        println!("Name:     {}", name);
+    }
+    println!(
+        "Battery:  {}",
+        status
+            .battery
+            .map(|x| format!("{}%", x))
+            .unwrap_or_else(unknown)
+    );
+    println!("Phase:    {}", status.phase.clone().unwrap_or_else(unknown));
+    println!(
+        "Dock:     {}",
+        if status.docked {
+            "docked"
+        } else {
+            "not docked"
+        }
+    );
+    println!(
+        "Bin:      {}",
+        match (status.bin_present, status.bin_full) {
+            (Some(false), _) => "missing",
+            (_, Some(true)) => "full",
+            (_, Some(false)) => "ok",
+            _ => "unknown",
+        }
+    );
+    match status.cycle.as_deref() {
+        None | Some("none") => println!("Mission:  none"),
+        Some(cycle) => println!(
+            "Mission:  {} ({} min, {} ft²)",
+            cycle,
+            status.mission_minutes.unwrap_or(0),
+            status.mission_area.unwrap_or(0)
+        ),
+    }
+    match status.error {
+        Some(0) | None => println!("Error:    none"),
+        Some(code) => println!("Error:    {}", code),
+    }
+}
@@ -1,8 +1,8 @@
-use super::Room;
+use crate::config::Room;
 use futures::channel::mpsc;
 use futures::select;
 use futures::stream::StreamExt;
-use roomba::{api, Client};
+use roomba::{api, Client, ConnectionState};
 use std::thread;
 use std::{error::Error, io};
 use termion::input::TermRead;
@@ -50,27 +50,31 @@ type Map = (String, String);
 pub struct App<'a> {
     client: &'a mut Client,
     events: Vec<(Vec<String>, String)>,
-    items: Vec<(Room, bool)>,
+    rooms: Picker,
+    zones: Picker,
+    zones_focused: bool,
     map: Option<Map>,
     running: bool,
-    state: ListState,
 }
 
 impl<'a> App<'a> {
-    pub fn new(client: &'a mut Client, rooms: &[Room], map: Option<Map>) -> Self {
-        let mut rooms = rooms.to_vec();
-        rooms.sort_by(|a, b| a.name.cmp(&b.name));
-
-        let mut state = ListState::default();
-        state.select(Some(0));
-
+    pub fn new(client: &'a mut Client, rooms: &[Room], zones: &[Room], map: Option<Map>) -> Self {
         Self {
             client,
             events: vec![],
-            items: rooms.into_iter().map(|x| (x, false)).collect(),
+            rooms: Picker::new(rooms),
+            zones: Picker::new(zones),
+            zones_focused: false,
             map,
             running: true,
-            state,
+        }
+    }
+
+    fn picker(&mut self) -> &mut Picker {
+        if self.zones_focused {
+            &mut self.zones
+        } else {
+            &mut self.rooms
         }
     }
 
@@ -136,11 +140,28 @@ impl<'a> App<'a> {
                     }
                 },
                 ev = self.client.events.next() => {
-                    if let Some(ev) = ev.flatten() {
-                        // TODO: log error in logger, not in user interface
-                        let _ = self.log_event(ev);
-                    } else {
-                        break
+                    match ev {
+                        Some(Some(ev)) => {
+                            // TODO: log error in logger, not in user interface
+                            let _ = self.log_event(ev);
+                        }
+                        Some(None) => {
+                            self.log(vec!["Connection to the robot lost".to_string()], "ERROR");
+                        }
+                        // stay open to look at the final state
+                        None if self.client.is_replaying() => {
+                            self.log(vec!["End of the replayed session".to_string()], "INFO");
+                        }
+                        None => break,
+                    }
+                },
+                state = self.client.states.next() => {
+                    if let Some(state) = state {
+                        let level = match state {
+                            ConnectionState::Connected => "INFO",
+                            ConnectionState::Disconnected => "ERROR",
+                        };
+                        self.log(vec![format!("Connection {}", state)], level);
                     }
                 },
                 complete => break,
@@ -160,27 +181,22 @@ impl<'a> App<'a> {
                 .constraints([Constraint::Percentage(25), Constraint::Percentage(75)].as_ref())
                 .split(f.size());
 
-            let items: Vec<ListItem> = self
-                .items
-                .iter()
-                .enumerate()
-                .map(|(i, room)| {
-                    let lines = vec![Spans::from(format!(
-                        "[{:>2}] {}",
-                        if room.1 {
-                            (i + 1).to_string()
-                        } else {
-                            String::new()
-                        },
-                        room.0
-                    ))];
-                    ListItem::new(lines)
-                })
-                .collect();
-            let items = List::new(items)
-                .block(Block::default().borders(Borders::ALL).title("Rooms"))
-                .highlight_style(Style::default().fg(Color::LightYellow));
-            f.render_stateful_widget(items, chunks[0], &mut self.state);
+            if self.zones.items.is_empty() {
+                let rooms = self.rooms.list("Rooms", 0, true);
+                f.render_stateful_widget(rooms, chunks[0], &mut self.rooms.state);
+            } else {
+                let pickers = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+                    .split(chunks[0]);
+
+                let rooms = self.rooms.list("Rooms", 0, !self.zones_focused);
+                let zones =
+                    self.zones
+                        .list("Zones", self.rooms.selected().count(), self.zones_focused);
+                f.render_stateful_widget(rooms, pickers[0], &mut self.rooms.state);
+                f.render_stateful_widget(zones, pickers[1], &mut self.zones.state);
+            }
 
             let events: Vec<ListItem> = self
                 .events
@@ -217,11 +233,14 @@ impl<'a> App<'a> {
             Key::Char('q') => {
                 self.running = false;
             }
-            Key::Down => self.next(),
-            Key::Up => self.previous(),
-            Key::Char(' ') => self.toggle(),
-            Key::Char('+') => self.move_up(),
-            Key::Char('-') => self.move_down(),
+            Key::Char('\t') if !self.zones.items.is_empty() => {
+                self.zones_focused ^= true;
+            }
+            Key::Down => self.picker().next(),
+            Key::Up => self.picker().previous(),
+            Key::Char(' ') => self.picker().toggle(),
+            Key::Char('+') => self.picker().move_up(),
+            Key::Char('-') => self.picker().move_down(),
             Key::Char('\n') => self.start_job().await,
             _ => {}
         }
@@ -230,17 +249,10 @@ impl<'a> App<'a> {
     async fn start_job(&mut self) {
         if let Some((pmap_id, user_pmapv_id)) = self.map.clone() {
             let rooms: Vec<_> = self
-                .items
-                .iter()
-                .filter_map(
-                    |(room, selected)| {
-                        if *selected {
-                            Some(room.clone())
-                        } else {
-                            None
-                        }
-                    },
-                )
+                .rooms
+                .selected()
+                .chain(self.zones.selected())
+                .cloned()
                 .collect();
             self.command(
                 rooms
@@ -276,6 +288,64 @@ impl<'a> App<'a> {
             );
         }
     }
+}
+
+/// A list of rooms or zones to select and to order.
+struct Picker {
+    items: Vec<(Room, bool)>,
+    state: ListState,
+}
+
+impl Picker {
+    fn new(regions: &[Room]) -> Self {
+        let mut regions = regions.to_vec();
+        regions.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut state = ListState::default();
+        state.select(Some(0));
+
+        Self {
+            items: regions.into_iter().map(|x| (x, false)).collect(),
+            state,
+        }
+    }
+
+    /// The selected regions in the order they will be cleaned.
+    fn selected(&self) -> impl Iterator<Item = &Room> {
+        self.items
+            .iter()
+            .filter(|(_, selected)| *selected)
+            .map(|(room, _)| room)
+    }
+
+    /// Render the regions, numbering the selected ones after the `offset` first regions to clean.
+    fn list(&self, title: &'static str, offset: usize, focused: bool) -> List<'static> {
+        let items: Vec<ListItem> = self
+            .items
+            .iter()
+            .enumerate()
+            .map(|(i, room)| {
+                let lines = vec![Spans::from(format!(
+                    "[{:>2}] {}",
+                    if room.1 {
+                        (offset + i + 1).to_string()
+                    } else {
+                        String::new()
+                    },
+                    room.0
+                ))];
+                ListItem::new(lines)
+            })
+            .collect();
+
+        List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(if focused {
+                Style::default().fg(Color::LightYellow)
+            } else {
+                Style::default()
+            })
+    }
 
     fn next(&mut self) {
         let i = match self.state.selected() {
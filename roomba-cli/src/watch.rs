@@ -0,0 +1,70 @@
+use crate::hooks::Hooks;
+use futures::select;
+use futures::stream::StreamExt;
+use roomba::{Client, State};
+use serde_json::json;
+use std::error::Error;
+use std::io::Write;
+use std::time::SystemTime;
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// Stream every inbound message, or only the high-level events, as newline-delimited JSON and run
+/// the hooks of the events.
+pub async fn run(
+    client: &mut Client,
+    events_only: bool,
+    hooks: &mut Hooks,
+) -> Result<(), Box<dyn Error>> {
+    let stdout = std::io::stdout();
+    let mut state = State::default();
+
+    loop {
+        let lines = select! {
+            ev = client.events.next() => match ev {
+                Some(Some(message)) => {
+                    let events = state.update(&message).unwrap_or_default();
+                    let status = state.status();
+                    for event in events.iter() {
+                        hooks.handle(event, &status);
+                    }
+
+                    if events_only {
+                        events
+                            .into_iter()
+                            .map(|event| json!({ "time": now(), "event": event }))
+                            .collect::<Vec<_>>()
+                    } else {
+                        let payload: serde_json::Value = serde_json::from_slice(message.payload())
+                            .unwrap_or_else(|_| message.payload_str().into());
+
+                        vec![json!({
+                            "time": now(),
+                            "topic": message.topic(),
+                            "payload": payload,
+                        })]
+                    }
+                }
+                Some(None) => return Err("connection to the robot lost".into()),
+                None => break,
+            },
+            ev = client.states.next() => match ev {
+                Some(connection) => vec![json!({ "time": now(), "connection": connection })],
+                None => continue,
+            },
+            complete => break,
+        };
+
+        let mut fh = stdout.lock();
+        for line in lines {
+            writeln!(fh, "{}", line)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use crate::config::Webhook;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde_json::Value;
+use std::thread;
+use std::time::Duration;
+
+const SIGNATURE_HEADER: &str = "X-Roomba-Signature";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Send the payload of an event to a webhook in the background, retrying on failure.
+pub fn deliver(webhook: &Webhook, payload: &Value) {
+    let webhook = webhook.clone();
+    let body = match webhook.template.as_ref() {
+        Some(template) => render(template, payload),
+        None => payload.to_string(),
+    };
+
+    thread::spawn(move || {
+        let signature = match webhook.secret.as_ref().map(|x| sign(x, &body)) {
+            Some(Ok(signature)) => Some(signature),
+            Some(Err(err)) => {
+                warn!("could not sign webhook for {}: {}", webhook.url, err);
+                return;
+            }
+            None => None,
+        };
+
+        for attempt in 0..=webhook.retries {
+            if attempt > 0 {
+                thread::sleep(Duration::from_secs(1 << (attempt - 1).min(6)));
+            }
+
+            let mut request = ureq::post(&webhook.url);
+            request
+                .set("Content-Type", "application/json")
+                .timeout(TIMEOUT);
+            if let Some(signature) = signature.as_ref() {
+                request.set(SIGNATURE_HEADER, signature);
+            }
+
+            let response = request.send_string(&body);
+            if response.ok() {
+                debug!("webhook delivered to {}", webhook.url);
+                return;
+            }
+
+            match response.synthetic_error() {
+                Some(err) => debug!("could not deliver webhook to {}: {}", webhook.url, err),
+                None => debug!(
+                    "could not deliver webhook to {}: HTTP {}",
+                    webhook.url,
+                    response.status()
+                ),
+            }
+        }
+
+        warn!(
+            "could not deliver webhook to {} after {} attempts",
+            webhook.url,
+            webhook.retries + 1
+        );
+    });
+}
+
+/// Replace the `{{name}}` placeholders of the template by the fields of the event and the status
+/// of the robot, escaped to be used inside JSON strings.
+fn render(template: &str, payload: &Value) -> String {
+    let mut fields = serde_json::Map::new();
+    for key in &["status", "event"] {
+        if let Some(object) = payload[key].as_object() {
+            fields.extend(object.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+    }
+    fields.insert("event".to_string(), payload["event"]["type"].clone());
+    fields.insert("robot".to_string(), payload["robot"].clone());
+
+    let mut out = template.to_string();
+    for (key, value) in fields {
+        let value = match value {
+            Value::Null => String::new(),
+            Value::String(x) => {
+                let escaped = Value::String(x).to_string();
+                escaped[1..escaped.len() - 1].to_string()
+            }
+            x => x.to_string(),
+        };
+        out = out.replace(&format!("{{{{{}}}}}", key), &value);
+    }
+
+    out
+}
+
+/// HMAC-SHA256 of the body as `sha256=<hex>`.
+fn sign(secret: &str, body: &str) -> Result<String, openssl::error::ErrorStack> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(body.as_bytes())?;
+    let hex: String = signer
+        .sign_to_vec()?
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect();
+
+    Ok(format!("sha256={}", hex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn payload() -> Value {
+        json!({
+            "robot": "kitchen",
+            "event": { "type": "mission_ended", "outcome": "done", "minutes": 42 },
+            "status": { "battery": 87, "phase": "charge", "error": null, "name": "Rob \"the\" bot" },
+        })
+    }
+
+    #[test]
+    fn render_fields() {
+        let body = render(
+            "{{robot}}: {{event}} {{outcome}} in {{minutes}} min (battery {{battery}}%)",
+            &payload(),
+        );
+
+        assert_eq!(body, "kitchen: mission_ended done in 42 min (battery 87%)");
+    }
+
+    #[test]
+    fn render_escapes_strings() {
+        let body = render(r#"{"text": "{{name}}"}"#, &payload());
+
+        assert_eq!(body, r#"{"text": "Rob \"the\" bot"}"#);
+        assert!(serde_json::from_str::<Value>(&body).is_ok());
+    }
+
+    #[test]
+    fn render_null_and_unknown() {
+        let body = render("[{{error}}] {{unknown}}", &payload());
+
+        assert_eq!(body, "[] {{unknown}}");
+    }
+
+    #[test]
+    fn signature() {
+        let signature = sign("key", "The quick brown fox jumps over the lazy dog").unwrap();
+
+        assert_eq!(
+            signature,
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
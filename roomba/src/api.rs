@@ -14,7 +14,9 @@ pub enum Message {
         #[serde(flatten)]
         extra: Option<Extra>,
     },
-    Delta,
+    Delta {
+        state: serde_json::Value,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -41,7 +43,7 @@ pub enum Extra {
     },
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub struct Region {
     pub region_id: String,
@@ -49,6 +51,24 @@ pub struct Region {
     pub type_: String,
 }
 
+/// Number of passes of the cleaning missions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Passes {
+    Auto,
+    One,
+    Two,
+}
+
+/// Suction power on carpets.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CarpetBoost {
+    Auto,
+    Performance,
+    Eco,
+}
+
 impl From<&str> for Region {
     fn from(s: &str) -> Self {
         Self {
@@ -73,10 +93,38 @@ impl Message {
         }
     }
 
+    /// Change the cleaning preferences of the robot, the ones left to `None` are kept.
+    pub fn new_preferences(passes: Option<Passes>, carpet_boost: Option<CarpetBoost>) -> Self {
+        let mut state = serde_json::Map::new();
+
+        if let Some(passes) = passes {
+            let (no_auto_passes, two_pass) = match passes {
+                Passes::Auto => (false, false),
+                Passes::One => (true, false),
+                Passes::Two => (true, true),
+            };
+            state.insert("noAutoPasses".to_string(), no_auto_passes.into());
+            state.insert("twoPass".to_string(), two_pass.into());
+        }
+        if let Some(carpet_boost) = carpet_boost {
+            let (carpet_boost, vac_high) = match carpet_boost {
+                CarpetBoost::Auto => (true, false),
+                CarpetBoost::Performance => (false, true),
+                CarpetBoost::Eco => (false, false),
+            };
+            state.insert("carpetBoost".to_string(), carpet_boost.into());
+            state.insert("vacHigh".to_string(), vac_high.into());
+        }
+
+        Self::Delta {
+            state: state.into(),
+        }
+    }
+
     pub fn topic(&self) -> &'static str {
         match self {
             Self::Cmd { .. } => "cmd",
-            _ => todo!(),
+            Self::Delta { .. } => "delta",
         }
     }
 
@@ -117,4 +165,21 @@ impl Info {
             Ok(suffix.unwrap().to_string())
         })
     }
+
+    /// Name of the robot given in the app.
+    pub fn name(&self) -> Option<&str> {
+        self.attr("robotname")
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.attr("sku")
+    }
+
+    pub fn firmware(&self) -> Option<&str> {
+        self.attr("sw")
+    }
+
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).and_then(|x| x.as_str())
+    }
 }
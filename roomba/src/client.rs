@@ -1,18 +1,39 @@
 use crate::api::{Info, Message};
+use crate::record::{self, Direction, Recorder};
+use crate::watchdog::{self, ConnectionState, Liveness, Watchdog};
+use futures::channel::mpsc;
 use futures::stream::{FusedStream, StreamExt};
 use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
 use std::collections::HashSet;
 use std::io::Read;
 use std::io::Write;
 use std::net::{TcpStream, UdpSocket};
+use std::path::Path;
 use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const DISCOVERY_PACKET: &[u8] = b"irobotmcs";
 const GET_PASSWORD_PACKET: &[u8] = &[0xf0, 0x05, 0xef, 0xcc, 0x3b, 0x29, 0x00];
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(15);
+/// Keep-alive interval of the MQTT connection, the default of the MQTT client.
+pub const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Return codes of a CONNACK refusing the client identifier (the BLID) and the credentials.
+const CONNACK_IDENTIFIER_REJECTED: i32 = 2;
+const CONNACK_BAD_USER_NAME_OR_PASSWORD: i32 = 4;
+const CONNACK_NOT_AUTHORIZED: i32 = 5;
+
+type Events = Box<dyn FusedStream<Item = Option<paho_mqtt::message::Message>> + Unpin>;
 
 pub struct Client {
     pub mqtt: paho_mqtt::AsyncClient,
-    pub events: Box<dyn FusedStream<Item = Option<paho_mqtt::message::Message>> + Unpin>,
+    pub events: Events,
+    /// Changes of the connection state, only yielded when a watchdog is running.
+    pub states: Box<dyn FusedStream<Item = ConnectionState> + Unpin>,
+    recorder: Option<Recorder>,
+    replaying: bool,
+    liveness: Option<Arc<Mutex<Liveness>>>,
 }
 
 impl Client {
@@ -21,6 +42,18 @@ impl Client {
         blid: B,
         password: P,
         buffer: usize,
+    ) -> paho_mqtt::Result<Self> {
+        Self::with_keep_alive(hostname, blid, password, buffer, KEEP_ALIVE).await
+    }
+
+    /// Like `Client::new` but the robot is pinged (MQTT PINGREQ) after `keep_alive` without
+    /// traffic. The connection is lost if it doesn't answer, which detects half-open connections.
+    pub async fn with_keep_alive<S: AsRef<str>, B: Into<String>, P: Into<String>>(
+        hostname: S,
+        blid: B,
+        password: P,
+        buffer: usize,
+        keep_alive: Duration,
     ) -> paho_mqtt::Result<Self> {
         let blid = blid.into();
         let uri = format!("ssl://{}:8883", hostname.as_ref());
@@ -41,6 +74,7 @@ impl Client {
             .user_name(blid)
             .password(password)
             .retry_interval(std::time::Duration::from_secs(3))
+            .keep_alive_interval(keep_alive)
             .finalize();
 
         let rx = client.get_stream(buffer);
@@ -49,19 +83,191 @@ impl Client {
         Ok(Self {
             mqtt: client,
             events: Box::new(rx.fuse()),
+            states: Box::new(futures::stream::empty::<ConnectionState>().fuse()),
+            recorder: None,
+            replaying: false,
+            liveness: None,
+        })
+    }
+
+    /// Like `Client::with_keep_alive` but when the robot cannot be reached, look for it on the
+    /// network by its BLID and try again with its new address. The new address is returned if it
+    /// changed. A refused BLID or password is returned as is.
+    pub async fn new_or_discover<S: AsRef<str>, B: Into<String>, P: Into<String>>(
+        hostname: S,
+        blid: B,
+        password: P,
+        buffer: usize,
+        keep_alive: Duration,
+    ) -> paho_mqtt::Result<(Self, Option<String>)> {
+        let blid = blid.into();
+        let password = password.into();
+
+        let err = match Self::with_keep_alive(
+            hostname.as_ref(),
+            blid.clone(),
+            password.clone(),
+            buffer,
+            keep_alive,
+        )
+        .await
+        {
+            Ok(client) => return Ok((client, None)),
+            // the robot answered: the address is right but the credentials are not
+            Err(err) if is_refused(&err) => return Err(err),
+            Err(err) => err,
+        };
+
+        debug!(
+            "could not connect to {}, looking for robot {} on the network...",
+            hostname.as_ref(),
+            blid
+        );
+        match Discovery::find(&blid, DISCOVERY_TIMEOUT) {
+            Ok(Some(info)) if info.ip != hostname.as_ref() => {
+                info!("robot {} found at new address: {}", blid, info.ip);
+                let client =
+                    Self::with_keep_alive(&info.ip, blid, password, buffer, keep_alive).await?;
+
+                Ok((client, Some(info.ip)))
+            }
+            Ok(_) => Err(err),
+            Err(discovery_err) => {
+                debug!("discovery failed: {}", discovery_err);
+                Err(err)
+            }
+        }
+    }
+
+    /// Create a client that doesn't connect to any robot but plays back the inbound messages of
+    /// a session file written by `Client::record`. A `speed` of 2.0 replays twice as fast.
+    pub fn replay<P: AsRef<Path>>(path: P, speed: f64) -> std::io::Result<Self> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "replay speed must be greater than zero",
+            ));
+        }
+
+        let opts = paho_mqtt::CreateOptionsBuilder::new()
+            .server_uri("tcp://localhost:1883")
+            .client_id("replay")
+            .finalize();
+        let client = paho_mqtt::AsyncClient::new(opts)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+        let rx = record::replay(path, speed)?;
+
+        Ok(Self {
+            mqtt: client,
+            events: Box::new(rx.fuse()),
+            states: Box::new(futures::stream::empty::<ConnectionState>().fuse()),
+            recorder: None,
+            replaying: true,
+            liveness: None,
         })
     }
 
+    /// Append every inbound and outbound message to a JSON Lines session file.
+    pub fn record<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let recorder = Recorder::create(path)?;
+        let events = self.take_events();
+        let inbound = recorder.clone();
+
+        self.events = Box::new(events.inspect(move |message| {
+            if let Some(message) = message {
+                inbound.record_message(Direction::Inbound, message);
+            }
+        }));
+        self.recorder = Some(recorder);
+
+        Ok(())
+    }
+
+    /// Monitor the connection to the robot and report when it is lost. Changes are yielded by
+    /// `Client::states`. The half-open connections are detected by the keep-alive of the
+    /// connection: create the client with `Client::with_keep_alive` to choose its interval.
+    ///
+    /// When `reconnect` is enabled, the lost connections are not yielded by `Client::events`
+    /// anymore as the watchdog re-establishes them.
+    pub fn watchdog(&mut self, watchdog: Watchdog) {
+        let (tx, rx) = mpsc::unbounded();
+        let liveness = Arc::new(Mutex::new(Liveness::new(tx)));
+        let events = self.take_events();
+        let inbound = liveness.clone();
+
+        self.events = Box::new(
+            events
+                .inspect(move |message| {
+                    let mut liveness = inbound.lock().unwrap();
+                    if message.is_some() {
+                        liveness.seen();
+                    } else {
+                        liveness.set_state(ConnectionState::Disconnected);
+                    }
+                })
+                .filter(move |message| {
+                    futures::future::ready(message.is_some() || !watchdog.reconnect)
+                }),
+        );
+        self.states = Box::new(rx);
+
+        if !self.replaying {
+            watchdog::spawn(self.mqtt.clone(), watchdog, liveness.clone());
+        }
+        self.liveness = Some(liveness);
+    }
+
+    /// Whether the client plays back a session file instead of being connected to a robot.
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        if let Some(liveness) = self.liveness.as_ref() {
+            liveness.lock().unwrap().state
+        } else if self.replaying || self.mqtt.is_connected() {
+            ConnectionState::Connected
+        } else {
+            ConnectionState::Disconnected
+        }
+    }
+
+    pub(crate) fn take_events(&mut self) -> Events {
+        std::mem::replace(
+            &mut self.events,
+            Box::new(futures::stream::empty::<Option<paho_mqtt::message::Message>>().fuse()),
+        )
+    }
+
     pub async fn send_message(&self, message: &Message) -> paho_mqtt::Result<()> {
-        self.mqtt
-            .publish(
-                paho_mqtt::MessageBuilder::new()
-                    .topic(message.topic())
-                    .payload(message.payload())
-                    .qos(0)
-                    .finalize(),
-            )
-            .await
+        self.publish(message.topic(), message.payload()).await
+    }
+
+    /// Publish an arbitrary payload to the robot.
+    pub async fn publish<T: Into<String>, P: Into<Vec<u8>>>(
+        &self,
+        topic: T,
+        payload: P,
+    ) -> paho_mqtt::Result<()> {
+        let message = paho_mqtt::MessageBuilder::new()
+            .topic(topic)
+            .payload(payload)
+            .qos(0)
+            .finalize();
+
+        if let Some(recorder) = self.recorder.as_ref() {
+            recorder.record_message(Direction::Outbound, &message);
+        }
+
+        if self.replaying {
+            debug!(
+                "replaying session, message not sent: {}",
+                message.payload_str()
+            );
+            return Ok(());
+        }
+
+        self.mqtt.publish(message).await
     }
 
     pub fn find_ip_address() -> std::io::Result<Discovery> {
@@ -124,6 +330,7 @@ impl Client {
 pub struct Discovery {
     socket: UdpSocket,
     found: HashSet<String>,
+    deadline: Option<Instant>,
 }
 
 impl Discovery {
@@ -135,8 +342,55 @@ impl Discovery {
         Ok(Discovery {
             socket,
             found: HashSet::new(),
+            deadline: None,
         })
     }
+
+    /// Look for the robot with the given BLID on the network, giving up after `timeout`.
+    pub fn find(blid: &str, timeout: Duration) -> std::io::Result<Option<Info>> {
+        let deadline = Instant::now() + timeout;
+        let mut discovery = Discovery::new()?;
+        discovery.deadline = Some(deadline);
+
+        for res in discovery {
+            match res {
+                Ok(info) if info.robot_id().ok().as_deref() == Some(blid) => return Ok(Some(info)),
+                Ok(info) => trace!("ignoring robot at {}", info.ip),
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(err) => return Err(err),
+            }
+
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Collect every robot answering on the network during `timeout`.
+    pub fn find_all(timeout: Duration) -> std::io::Result<Vec<Info>> {
+        let deadline = Instant::now() + timeout;
+        let mut robots = Vec::new();
+        let mut discovery = Discovery::new()?;
+        discovery.deadline = Some(deadline);
+
+        for res in discovery {
+            match res {
+                Ok(info) => robots.push(info),
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(err) => return Err(err),
+            }
+
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        Ok(robots)
+    }
 }
 
 impl Iterator for Discovery {
@@ -156,6 +410,14 @@ impl Iterator for Discovery {
                 Some(Err(err))
             }
             Ok(_) => loop {
+                // robots already found may keep answering
+                if matches!(self.deadline, Some(x) if Instant::now() >= x) {
+                    break Some(Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "discovery timed out",
+                    )));
+                }
+
                 match self.socket.recv(&mut data) {
                     Err(err) => {
                         debug!("error receiving discovery packet: {}", err);
@@ -178,3 +440,40 @@ impl Iterator for Discovery {
         }
     }
 }
+
+/// Whether the robot refused the BLID or the password, as opposed to a network error. The MQTT
+/// client reports the return code of the CONNACK, its own errors are negative.
+pub fn is_refused(err: &paho_mqtt::Error) -> bool {
+    match err {
+        paho_mqtt::Error::Paho(code) | paho_mqtt::Error::PahoDescr(code, _) => matches!(
+            *code,
+            CONNACK_IDENTIFIER_REJECTED
+                | CONNACK_BAD_USER_NAME_OR_PASSWORD
+                | CONNACK_NOT_AUTHORIZED
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refused_credentials() {
+        assert!(is_refused(&paho_mqtt::Error::Paho(
+            CONNACK_BAD_USER_NAME_OR_PASSWORD
+        )));
+        assert!(is_refused(&paho_mqtt::Error::PahoDescr(
+            CONNACK_NOT_AUTHORIZED,
+            "Not authorized".to_string()
+        )));
+        assert!(is_refused(&paho_mqtt::Error::Paho(
+            CONNACK_IDENTIFIER_REJECTED
+        )));
+        // server unavailable: the robot may accept the connection later
+        assert!(!is_refused(&paho_mqtt::Error::Paho(3)));
+        // errors of the MQTT client, e.g. the TCP connection failed
+        assert!(!is_refused(&paho_mqtt::Error::Paho(-1)));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::api::Message;
+use crate::Client;
+use futures::stream::{SelectAll, Stream, StreamExt};
+use std::collections::HashMap;
+
+const ALREADY_IN_FLEET: &str = "the robot is already in the fleet";
+
+/// A message received from one of the robots of a `Fleet`.
+#[derive(Debug)]
+pub struct FleetEvent {
+    pub blid: String,
+    pub message: Option<paho_mqtt::message::Message>,
+}
+
+/// Several robots managed as a unit, keyed by BLID.
+///
+/// The events of every robot are aggregated in `Fleet::events`, so the clients of a fleet don't
+/// yield any event themselves.
+#[derive(Default)]
+pub struct Fleet {
+    clients: HashMap<String, Client>,
+    events: SelectAll<Box<dyn Stream<Item = FleetEvent> + Unpin>>,
+}
+
+impl Fleet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn connect<S: AsRef<str>, B: Into<String>, P: Into<String>>(
+        &mut self,
+        hostname: S,
+        blid: B,
+        password: P,
+        buffer: usize,
+    ) -> paho_mqtt::Result<()> {
+        let blid = blid.into();
+        // the robot accepts only one connection at a time
+        if self.clients.contains_key(&blid) {
+            return Err(paho_mqtt::Error::General(ALREADY_IN_FLEET));
+        }
+
+        let client = Client::new(hostname, blid.clone(), password, buffer).await?;
+        self.insert(blid, client)
+            .map_err(|_| paho_mqtt::Error::General(ALREADY_IN_FLEET))
+    }
+
+    /// Add a connected client to the fleet. The client is given back if a robot with the same BLID
+    /// is already in the fleet, as the events of its client can't be taken back.
+    pub fn insert<B: Into<String>>(&mut self, blid: B, mut client: Client) -> Result<(), Client> {
+        let blid = blid.into();
+        if self.clients.contains_key(&blid) {
+            return Err(client);
+        }
+        let id = blid.clone();
+
+        self.events
+            .push(Box::new(client.take_events().map(move |message| {
+                FleetEvent {
+                    blid: id.clone(),
+                    message,
+                }
+            })));
+        self.clients.insert(blid, client);
+
+        Ok(())
+    }
+
+    pub fn get(&self, blid: &str) -> Option<&Client> {
+        self.clients.get(blid)
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = (&str, &Client)> {
+        self.clients
+            .iter()
+            .map(|(blid, client)| (blid.as_str(), client))
+    }
+
+    /// The messages of every robot of the fleet, tagged with the BLID of the robot.
+    pub fn events(&mut self) -> impl Stream<Item = FleetEvent> + Unpin + '_ {
+        &mut self.events
+    }
+
+    pub fn blids(&self) -> impl Iterator<Item = &str> {
+        self.clients.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Send a message to every robot of the fleet and return the result for each of them.
+    pub async fn broadcast(&self, message: &Message) -> Vec<(String, paho_mqtt::Result<()>)> {
+        futures::future::join_all(self.clients.iter().map(|(blid, client)| async move {
+            (blid.clone(), client.send_message(message).await)
+        }))
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{Direction, Record};
+
+    /// A session file with one message of the robot, replayed by an offline client.
+    fn replay(name: &str, topic: &str) -> Client {
+        let path = std::env::temp_dir().join(format!(
+            "roomba-fleet-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let record = Record::new(Direction::Inbound, topic, "{}");
+        std::fs::write(&path, serde_json::to_string(&record).unwrap() + "\n").unwrap();
+        let client = Client::replay(&path, 1.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        client
+    }
+
+    #[test]
+    fn duplicate_robot() {
+        let mut fleet = Fleet::new();
+
+        assert!(fleet.insert("blid", replay("first", "a")).is_ok());
+        assert!(fleet.insert("blid", replay("second", "b")).is_err());
+        assert_eq!(fleet.len(), 1);
+        assert_eq!(fleet.blids().collect::<Vec<_>>(), vec!["blid"]);
+    }
+
+    #[test]
+    fn events_tagged_with_blid() {
+        let mut fleet = Fleet::new();
+        fleet.insert("blid-a", replay("a", "topic-a")).ok().unwrap();
+        fleet.insert("blid-b", replay("b", "topic-b")).ok().unwrap();
+
+        let mut events: Vec<_> = futures::executor::block_on(fleet.events().collect::<Vec<_>>())
+            .into_iter()
+            .map(|x| (x.blid, x.message.unwrap().topic().to_string()))
+            .collect();
+        events.sort();
+
+        assert_eq!(
+            events,
+            vec![
+                ("blid-a".to_string(), "topic-a".to_string()),
+                ("blid-b".to_string(), "topic-b".to_string()),
+            ]
+        );
+        assert_eq!(fleet.clients().count(), 2);
+    }
+}
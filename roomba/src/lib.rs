@@ -3,5 +3,12 @@ extern crate log;
 
 pub mod api;
 pub mod client;
+pub mod fleet;
+pub mod record;
+pub mod state;
+pub mod watchdog;
 
 pub use client::*;
+pub use fleet::{Fleet, FleetEvent};
+pub use state::{Event, MissionOutcome, State, Status};
+pub use watchdog::{ConnectionState, Watchdog};
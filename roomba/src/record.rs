@@ -0,0 +1,125 @@
+use futures::channel::mpsc;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// A single MQTT message as stored in a session file (one JSON object per line).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub time: f64,
+    pub direction: Direction,
+    pub topic: String,
+    pub payload: String,
+}
+
+impl Record {
+    pub fn new<T: Into<String>, P: Into<String>>(
+        direction: Direction,
+        topic: T,
+        payload: P,
+    ) -> Self {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+
+        Self {
+            time,
+            direction,
+            topic: topic.into(),
+            payload: payload.into(),
+        }
+    }
+}
+
+/// Appends every message going through a `Client` to a JSON Lines file.
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<LineWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        Ok(Self {
+            file: Arc::new(Mutex::new(LineWriter::new(file))),
+        })
+    }
+
+    pub fn record(&self, record: &Record) {
+        let line = serde_json::to_string(record).expect("serialization failed");
+        let mut file = self.file.lock().unwrap();
+
+        if let Err(err) = writeln!(file, "{}", line) {
+            warn!("could not write session record: {}", err);
+        }
+    }
+
+    pub fn record_message(&self, direction: Direction, message: &paho_mqtt::message::Message) {
+        self.record(&Record::new(
+            direction,
+            message.topic(),
+            message.payload_str(),
+        ));
+    }
+}
+
+/// Read a session file and feed its inbound messages to a channel, respecting the original
+/// delays between messages divided by `speed`.
+pub(crate) fn replay<P: AsRef<Path>>(
+    path: P,
+    speed: f64,
+) -> std::io::Result<mpsc::UnboundedReceiver<Option<paho_mqtt::message::Message>>> {
+    let reader = BufReader::new(File::open(path)?);
+    let (tx, rx) = mpsc::unbounded();
+
+    thread::spawn(move || {
+        let mut previous = None;
+
+        for (i, line) in reader.lines().enumerate() {
+            let record = match line
+                .map_err(|err| err.to_string())
+                .and_then(|x| serde_json::from_str::<Record>(&x).map_err(|err| err.to_string()))
+            {
+                Ok(record) if record.direction == Direction::Inbound => record,
+                Ok(_) => continue,
+                Err(err) => {
+                    warn!("could not read session record at line {}: {}", i + 1, err);
+                    continue;
+                }
+            };
+
+            if let Some(previous) = previous {
+                let delay = (record.time - previous) / speed;
+                if delay > 0.0 {
+                    thread::sleep(Duration::from_secs_f64(delay));
+                }
+            }
+            previous = Some(record.time);
+
+            let message = paho_mqtt::message::Message::new(record.topic, record.payload, 0);
+            if tx.unbounded_send(Some(message)).is_err() {
+                break;
+            }
+        }
+
+        debug!("end of replayed session");
+    });
+
+    Ok(rx)
+}
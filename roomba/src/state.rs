@@ -0,0 +1,461 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Phases of `cleanMissionStatus` where the robot sits on its dock.
+const DOCKED_PHASES: &[&str] = &["charge", "evac", "chargingerror"];
+/// Cycles of `cleanMissionStatus` that are cleaning missions. The others (`none`, `dock`, `evac`)
+/// are not.
+const MISSION_CYCLES: &[&str] = &["clean", "quick", "spot", "train", "manual"];
+
+/// Whether the cycle of `cleanMissionStatus` is a cleaning mission.
+pub fn is_mission_cycle(cycle: &str) -> bool {
+    MISSION_CYCLES.contains(&cycle)
+}
+
+/// The reported shadow of the robot, merged from every message received.
+#[derive(Debug, Clone)]
+pub struct State {
+    reported: Value,
+    returning_after_mission: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            reported: Value::Object(Default::default()),
+            returning_after_mission: false,
+        }
+    }
+}
+
+/// A high-level change of the state of the robot.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Battery {
+        level: u64,
+    },
+    Phase {
+        phase: String,
+    },
+    MissionStarted {
+        cycle: String,
+    },
+    MissionEnded {
+        outcome: MissionOutcome,
+        minutes: Option<u64>,
+        area: Option<u64>,
+    },
+    Stuck,
+    Error {
+        code: u64,
+    },
+    BinFull,
+    EvacStarted,
+    EvacDone,
+    Docked,
+}
+
+impl Event {
+    /// The names of the events, as serialized in the `type` field.
+    pub const NAMES: &'static [&'static str] = &[
+        "battery",
+        "phase",
+        "mission_started",
+        "mission_ended",
+        "stuck",
+        "error",
+        "bin_full",
+        "evac_started",
+        "evac_done",
+        "docked",
+    ];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissionOutcome {
+    /// The robot went back to its dock after finishing the mission.
+    Completed,
+    /// The mission has been stopped or the robot has been sent back to its dock.
+    Cancelled,
+}
+
+/// A summary of the state of the robot.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Status {
+    pub name: Option<String>,
+    pub battery: Option<u64>,
+    pub phase: Option<String>,
+    pub cycle: Option<String>,
+    pub docked: bool,
+    pub bin_present: Option<bool>,
+    pub bin_full: Option<bool>,
+    /// Elapsed time of the current mission in minutes.
+    pub mission_minutes: Option<u64>,
+    /// Area cleaned during the current mission in square feet.
+    pub mission_area: Option<u64>,
+    pub error: Option<u64>,
+    pub not_ready: Option<u64>,
+}
+
+impl Status {
+    pub fn is_stuck(&self) -> bool {
+        self.phase.as_deref() == Some("stuck") || self.error.unwrap_or(0) != 0
+    }
+
+    /// Whether the robot is on a cleaning mission.
+    pub fn in_mission(&self) -> bool {
+        self.cycle.as_deref().map(is_mission_cycle).unwrap_or(false)
+    }
+
+    pub fn needs_attention(&self) -> bool {
+        self.bin_full == Some(true)
+            || self.bin_present == Some(false)
+            || self.not_ready.unwrap_or(0) != 0
+    }
+}
+
+impl State {
+    /// Merge the reported state of a message and return the high-level events it caused.
+    /// Returns `None` if the message doesn't contain any reported state.
+    pub fn update(&mut self, message: &paho_mqtt::message::Message) -> Option<Vec<Event>> {
+        let payload = match serde_json::from_slice::<Value>(message.payload()) {
+            Ok(payload) => payload,
+            Err(err) => {
+                debug!("could not parse message on {}: {}", message.topic(), err);
+                return None;
+            }
+        };
+        let reported = payload.pointer("/state/reported")?;
+
+        let before = self.status();
+        merge(&mut self.reported, reported);
+        let after = self.status();
+
+        Some(self.diff(&before, &after))
+    }
+
+    fn diff(&mut self, before: &Status, after: &Status) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        if let (Some(_), Some(level)) = (before.battery, after.battery) {
+            if before.battery != after.battery {
+                events.push(Event::Battery { level });
+            }
+        }
+
+        if let (Some(previous), Some(phase)) = (before.phase.as_deref(), after.phase.as_deref()) {
+            if previous != phase {
+                events.push(Event::Phase {
+                    phase: phase.to_string(),
+                });
+
+                match (previous, phase) {
+                    (_, "stuck") => events.push(Event::Stuck),
+                    (_, "evac") => events.push(Event::EvacStarted),
+                    ("evac", _) => events.push(Event::EvacDone),
+                    (_, "hmPostMsn") => self.returning_after_mission = true,
+                    _ => {}
+                }
+
+                if after.docked && !before.docked {
+                    events.push(Event::Docked);
+                }
+            }
+        }
+
+        if let (Some(previous), Some(cycle)) = (before.cycle.as_deref(), after.cycle.as_deref()) {
+            match (is_mission_cycle(previous), is_mission_cycle(cycle)) {
+                (false, true) => {
+                    self.returning_after_mission = false;
+                    events.push(Event::MissionStarted {
+                        cycle: cycle.to_string(),
+                    });
+                }
+                (true, false) => {
+                    events.push(Event::MissionEnded {
+                        outcome: if self.returning_after_mission {
+                            MissionOutcome::Completed
+                        } else {
+                            MissionOutcome::Cancelled
+                        },
+                        minutes: before.mission_minutes,
+                        area: before.mission_area,
+                    });
+                    self.returning_after_mission = false;
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some(previous), Some(code)) = (before.error, after.error) {
+            if previous != code && code != 0 {
+                events.push(Event::Error { code });
+            }
+        }
+
+        if before.bin_full == Some(false) && after.bin_full == Some(true) {
+            events.push(Event::BinFull);
+        }
+
+        events
+    }
+
+    pub fn reported(&self) -> &Value {
+        &self.reported
+    }
+
+    /// Get a value of the reported state using a JSON pointer (e.g. `/bin/full`).
+    pub fn get(&self, pointer: &str) -> Option<&Value> {
+        self.reported.pointer(pointer)
+    }
+
+    /// Whether enough of the state has been received to describe the robot.
+    pub fn is_synced(&self) -> bool {
+        ["/batPct", "/cleanMissionStatus", "/bin"]
+            .iter()
+            .all(|x| self.get(x).is_some())
+    }
+
+    pub fn battery(&self) -> Option<u64> {
+        self.get("/batPct").and_then(Value::as_u64)
+    }
+
+    pub fn phase(&self) -> Option<&str> {
+        self.get("/cleanMissionStatus/phase")
+            .and_then(Value::as_str)
+    }
+
+    pub fn status(&self) -> Status {
+        let get_str = |x| self.get(x).and_then(Value::as_str).map(String::from);
+        let get_u64 = |x| self.get(x).and_then(Value::as_u64);
+        let get_bool = |x| self.get(x).and_then(Value::as_bool);
+
+        Status {
+            name: get_str("/name"),
+            battery: self.battery(),
+            phase: self.phase().map(String::from),
+            cycle: get_str("/cleanMissionStatus/cycle"),
+            docked: self
+                .phase()
+                .map(|x| DOCKED_PHASES.contains(&x))
+                .unwrap_or(false),
+            bin_present: get_bool("/bin/present"),
+            bin_full: get_bool("/bin/full"),
+            mission_minutes: get_u64("/cleanMissionStatus/mssnM"),
+            mission_area: get_u64("/cleanMissionStatus/sqft"),
+            error: get_u64("/cleanMissionStatus/error"),
+            not_ready: get_u64("/cleanMissionStatus/notReady"),
+        }
+    }
+}
+
+pub(crate) fn merge(target: &mut Value, value: &Value) {
+    match (target, value) {
+        (Value::Object(target), Value::Object(value)) => {
+            for (key, value) in value {
+                merge(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, value) => *target = value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn update(state: &mut State, reported: Value) -> Vec<Event> {
+        let payload = json!({ "state": { "reported": reported } }).to_string();
+        let message = paho_mqtt::Message::new("$aws/things/blid/shadow/update", payload, 0);
+
+        state.update(&message).unwrap()
+    }
+
+    fn mission(phase: &str, cycle: &str) -> Value {
+        json!({ "cleanMissionStatus": { "phase": phase, "cycle": cycle, "mssnM": 5, "sqft": 60 } })
+    }
+
+    fn docked() -> State {
+        let mut state = State::default();
+        update(&mut state, mission("charge", "none"));
+        state
+    }
+
+    fn mission_events(events: &[Event]) -> Vec<&Event> {
+        events
+            .iter()
+            .filter(|x| matches!(x, Event::MissionStarted { .. } | Event::MissionEnded { .. }))
+            .collect()
+    }
+
+    #[test]
+    fn merge_keeps_the_other_keys() {
+        let mut target = json!({ "bin": { "present": true, "full": false }, "batPct": 80 });
+        merge(&mut target, &json!({ "bin": { "full": true } }));
+
+        assert_eq!(
+            target,
+            json!({ "bin": { "present": true, "full": true }, "batPct": 80 })
+        );
+    }
+
+    #[test]
+    fn first_message_has_no_event() {
+        let mut state = State::default();
+
+        assert_eq!(update(&mut state, mission("run", "clean")), vec![]);
+    }
+
+    #[test]
+    fn phase_event_comes_before_mission_started() {
+        let mut state = docked();
+
+        assert_eq!(
+            update(&mut state, mission("run", "clean")),
+            vec![
+                Event::Phase {
+                    phase: "run".to_string()
+                },
+                Event::MissionStarted {
+                    cycle: "clean".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mission_completed_after_returning_to_the_dock() {
+        let mut state = docked();
+        update(&mut state, mission("run", "clean"));
+        update(&mut state, mission("hmPostMsn", "clean"));
+
+        assert_eq!(
+            update(&mut state, mission("charge", "none")),
+            vec![
+                Event::Phase {
+                    phase: "charge".to_string()
+                },
+                Event::Docked,
+                Event::MissionEnded {
+                    outcome: MissionOutcome::Completed,
+                    minutes: Some(5),
+                    area: Some(60),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mission_cancelled_when_stopped() {
+        let mut state = docked();
+        update(&mut state, mission("run", "clean"));
+        update(&mut state, mission("stop", "clean"));
+
+        assert_eq!(
+            mission_events(&update(&mut state, mission("stop", "none"))),
+            vec![&Event::MissionEnded {
+                outcome: MissionOutcome::Cancelled,
+                minutes: Some(5),
+                area: Some(60),
+            }]
+        );
+    }
+
+    #[test]
+    fn mission_cancelled_when_sent_to_the_dock() {
+        let mut state = docked();
+        update(&mut state, mission("run", "clean"));
+
+        let events = update(&mut state, mission("hmUsrDock", "dock"));
+        assert_eq!(
+            mission_events(&events),
+            vec![&Event::MissionEnded {
+                outcome: MissionOutcome::Cancelled,
+                minutes: Some(5),
+                area: Some(60),
+            }]
+        );
+    }
+
+    #[test]
+    fn completed_is_reset_by_a_new_mission() {
+        let mut state = docked();
+        update(&mut state, mission("run", "clean"));
+        update(&mut state, mission("hmPostMsn", "clean"));
+        update(&mut state, mission("charge", "none"));
+        update(&mut state, mission("run", "quick"));
+
+        assert_eq!(
+            mission_events(&update(&mut state, mission("stop", "none"))),
+            vec![&Event::MissionEnded {
+                outcome: MissionOutcome::Cancelled,
+                minutes: Some(5),
+                area: Some(60),
+            }]
+        );
+    }
+
+    #[test]
+    fn docking_and_evac_are_not_missions() {
+        let mut state = docked();
+        let mut events = update(&mut state, mission("hmUsrDock", "dock"));
+        events.extend(update(&mut state, mission("charge", "none")));
+        events.extend(update(&mut state, mission("evac", "evac")));
+        events.extend(update(&mut state, mission("charge", "none")));
+
+        assert_eq!(mission_events(&events), Vec::<&Event>::new());
+        assert!(events.contains(&Event::EvacStarted));
+        assert!(events.contains(&Event::EvacDone));
+    }
+
+    #[test]
+    fn stuck_and_errors() {
+        let mut state = docked();
+        update(&mut state, mission("run", "clean"));
+        update(
+            &mut state,
+            json!({ "cleanMissionStatus": { "phase": "run", "error": 0 } }),
+        );
+
+        let events = update(
+            &mut state,
+            json!({ "cleanMissionStatus": { "phase": "stuck", "error": 17 } }),
+        );
+        assert!(events.contains(&Event::Stuck));
+        assert!(events.contains(&Event::Error { code: 17 }));
+        assert!(state.status().is_stuck());
+    }
+
+    #[test]
+    fn event_names() {
+        let events = vec![
+            Event::Battery { level: 0 },
+            Event::Phase {
+                phase: String::new(),
+            },
+            Event::MissionStarted {
+                cycle: String::new(),
+            },
+            Event::MissionEnded {
+                outcome: MissionOutcome::Completed,
+                minutes: None,
+                area: None,
+            },
+            Event::Stuck,
+            Event::Error { code: 0 },
+            Event::BinFull,
+            Event::EvacStarted,
+            Event::EvacDone,
+            Event::Docked,
+        ];
+        let names: Vec<_> = events
+            .iter()
+            .map(|x| serde_json::to_value(x).unwrap()["type"].clone())
+            .collect();
+
+        assert_eq!(names, Event::NAMES.to_vec());
+    }
+}
@@ -0,0 +1,99 @@
+use futures::channel::mpsc;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connected,
+    /// The connection was lost, including when the robot did not answer the keep-alive.
+    Disconnected,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Connected => write!(f, "connected"),
+            Self::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Watchdog {
+    /// Time given to each attempt to reconnect. The keep-alive interval detecting the half-open
+    /// connections is chosen when the client is created, see `Client::with_keep_alive`.
+    pub timeout: Duration,
+    /// Reconnect when the connection is lost.
+    pub reconnect: bool,
+}
+
+pub(crate) struct Liveness {
+    last_seen: Instant,
+    pub(crate) state: ConnectionState,
+    tx: mpsc::UnboundedSender<ConnectionState>,
+}
+
+impl Liveness {
+    pub(crate) fn new(tx: mpsc::UnboundedSender<ConnectionState>) -> Self {
+        Self {
+            last_seen: Instant::now(),
+            state: ConnectionState::Connected,
+            tx,
+        }
+    }
+
+    pub(crate) fn seen(&mut self) {
+        self.last_seen = Instant::now();
+        self.set_state(ConnectionState::Connected);
+    }
+
+    pub(crate) fn set_state(&mut self, state: ConnectionState) {
+        if self.state != state {
+            debug!("connection state: {}", state);
+            self.state = state;
+            let _ = self.tx.unbounded_send(state);
+        }
+    }
+}
+
+pub(crate) fn spawn(
+    mqtt: paho_mqtt::AsyncClient,
+    watchdog: Watchdog,
+    liveness: Arc<Mutex<Liveness>>,
+) {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+
+        let state = {
+            let liveness = liveness.lock().unwrap();
+            if liveness.tx.is_closed() {
+                break;
+            }
+
+            liveness.state
+        };
+
+        match state {
+            // the MQTT client drops the connection when the keep-alive is not answered
+            ConnectionState::Connected if !mqtt.is_connected() => {
+                let mut liveness = liveness.lock().unwrap();
+                trace!(
+                    "connection dropped after {:?} without traffic",
+                    liveness.last_seen.elapsed()
+                );
+                liveness.set_state(ConnectionState::Disconnected);
+            }
+            ConnectionState::Disconnected if watchdog.reconnect => {
+                debug!("reconnecting...");
+                match mqtt.reconnect().wait_for(watchdog.timeout) {
+                    Ok(_) => liveness.lock().unwrap().seen(),
+                    Err(err) => debug!("could not reconnect: {}", err),
+                }
+            }
+            _ => {}
+        }
+    });
+}